@@ -0,0 +1,163 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use crate::db::filename::{lock_file_name, parse_file_name, FileType};
+
+/// Destroy the contents of the specified database.
+/// Be very careful using this method.
+///
+/// Only files that leveldb owns (as recognized by `parse_file_name`) are
+/// removed; anything else in the directory is left alone, and the directory
+/// itself is only removed if it ends up empty.
+///
+/// Return an error if the database is in use by another process, or if an
+/// owned file could not be removed. A missing directory is not an error.
+pub fn destroy_db(dbname: &str) -> io::Result<()> {
+    let entries = match fs::read_dir(dbname) {
+        Ok(entries) => entries,
+        // Ignore error in case directory does not exist
+        Err(_) => return Ok(()),
+    };
+
+    let lockname = lock_file_name(dbname);
+    let lock = lock_file(&lockname)?;
+
+    let mut result = Ok(());
+    for entry in entries {
+        let entry = entry?;
+        let filename = entry.file_name();
+        let filename = match filename.to_str() {
+            Some(filename) => filename,
+            None => continue,
+        };
+        match parse_file_name(filename) {
+            // Lock file will be deleted at end
+            Some((_, FileType::DBLockFile)) | None => {}
+            Some(_) => {
+                let del = fs::remove_file(entry.path());
+                if result.is_ok() && del.is_err() {
+                    result = del;
+                }
+            }
+        }
+    }
+
+    // Closing the file releases the lock
+    drop(lock);
+    let _ = fs::remove_file(&lockname);
+    // Ignore error in case dir contains other files
+    let _ = fs::remove_dir(dbname);
+
+    result
+}
+
+/// Lock the file named `fname` to prevent concurrent access to the database
+/// by multiple processes. The lock is released when the returned file is
+/// dropped.
+fn lock_file<P: AsRef<Path>>(fname: P) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&fname)?;
+    try_lock_exclusive(&file).map_err(|e| {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            io::Error::other(format!(
+                "lock {}: already held by process",
+                fname.as_ref().display()
+            ))
+        } else {
+            e
+        }
+    })?;
+    Ok(file)
+}
+
+#[cfg(unix)]
+fn try_lock_exclusive(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // flock() locks belong to the open file, so a second open of the same
+    // file conflicts even within this process, unlike fcntl() locks.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// No advisory locking on other platforms yet.
+#[cfg(not(unix))]
+fn try_lock_exclusive(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{destroy_db, lock_file};
+    use crate::db::filename::{current_file_name, lock_file_name, log_file_name, table_file_name};
+    use std::fs;
+    use std::path::Path;
+
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("leveldb-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn destroy_missing() {
+        let dbname = test_dir("destroy_missing");
+        fs::remove_dir(&dbname).unwrap();
+        assert!(destroy_db(&dbname).is_ok());
+    }
+
+    #[test]
+    fn destroy_owned_files_only() {
+        let dbname = test_dir("destroy_owned");
+        let owned = [
+            current_file_name(&dbname),
+            log_file_name(&dbname, 3),
+            table_file_name(&dbname, 5),
+            lock_file_name(&dbname),
+        ];
+        for fname in owned.iter() {
+            fs::write(fname, b"x").unwrap();
+        }
+        let foreign = format!("{}/not-a-leveldb-file", dbname);
+        fs::write(&foreign, b"x").unwrap();
+
+        assert!(destroy_db(&dbname).is_ok());
+        for fname in owned.iter() {
+            assert!(!Path::new(fname).exists(), "{}", fname);
+        }
+        // The foreign file keeps the directory alive.
+        assert!(Path::new(&foreign).exists());
+
+        fs::remove_file(&foreign).unwrap();
+        assert!(destroy_db(&dbname).is_ok());
+        assert!(!Path::new(&dbname).exists());
+    }
+
+    #[test]
+    fn destroy_locked() {
+        let dbname = test_dir("destroy_locked");
+        let log = log_file_name(&dbname, 1);
+        fs::write(&log, b"x").unwrap();
+
+        let lock = lock_file(lock_file_name(&dbname)).unwrap();
+        assert!(destroy_db(&dbname).is_err());
+        assert!(Path::new(&log).exists());
+
+        drop(lock);
+        assert!(destroy_db(&dbname).is_ok());
+        assert!(!Path::new(&dbname).exists());
+    }
+}
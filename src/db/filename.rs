@@ -0,0 +1,216 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+/// The kinds of files that a database directory may contain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    LogFile,
+    DBLockFile,
+    TableFile,
+    DescriptorFile,
+    CurrentFile,
+    TempFile,
+    // Either the current one, or an old one
+    InfoLogFile,
+}
+
+fn make_file_name(dbname: &str, number: u64, suffix: &str) -> String {
+    format!("{}/{:06}.{}", dbname, number, suffix)
+}
+
+/// Return the name of the log file with the specified number
+/// in the db named by `dbname`. The result will be prefixed with `dbname`.
+pub fn log_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "log")
+}
+
+/// Return the name of the sstable with the specified number
+/// in the db named by `dbname`. The result will be prefixed with `dbname`.
+pub fn table_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "ldb")
+}
+
+/// Return the legacy file name for an sstable with the specified number
+/// in the db named by `dbname`. The result will be prefixed with `dbname`.
+pub fn sst_table_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "sst")
+}
+
+/// Return the name of the descriptor file for the db named by
+/// `dbname` and the specified incarnation number. The result will be
+/// prefixed with `dbname`.
+pub fn descriptor_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    format!("{}/MANIFEST-{:06}", dbname, number)
+}
+
+/// Return the name of the current file. This file contains the name
+/// of the current manifest file. The result will be prefixed with `dbname`.
+pub fn current_file_name(dbname: &str) -> String {
+    format!("{}/CURRENT", dbname)
+}
+
+/// Return the name of the lock file for the db named by `dbname`.
+/// The result will be prefixed with `dbname`.
+pub fn lock_file_name(dbname: &str) -> String {
+    format!("{}/LOCK", dbname)
+}
+
+/// Return the name of a temporary file owned by the db named `dbname`.
+/// The result will be prefixed with `dbname`.
+pub fn temp_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "dbtmp")
+}
+
+/// Return the name of the info log file for `dbname`.
+pub fn info_log_file_name(dbname: &str) -> String {
+    format!("{}/LOG", dbname)
+}
+
+/// Return the name of the old info log file for `dbname`.
+pub fn old_info_log_file_name(dbname: &str) -> String {
+    format!("{}/LOG.old", dbname)
+}
+
+/// Parse a leading decimal number off `input`.
+///
+/// Return the number and the unparsed remainder, or `None` if `input` doesn't
+/// start with a digit or the number overflows a u64.
+fn consume_decimal_number(input: &str) -> Option<(u64, &str)> {
+    let digits = input.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for b in input[..digits].bytes() {
+        value = value
+            .checked_mul(10)?
+            .checked_add((b - b'0') as u64)?;
+    }
+    Some((value, &input[digits..]))
+}
+
+/// If `filename` is a leveldb file, return the number encoded in the file
+/// name together with the type of the file. Otherwise return `None`.
+///
+/// Owned filenames have the form:
+///    dbname/CURRENT
+///    dbname/LOCK
+///    dbname/LOG
+///    dbname/LOG.old
+///    dbname/MANIFEST-[0-9]+
+///    dbname/[0-9]+.(log|sst|ldb|dbtmp)
+///
+/// `filename` is the last component of the path, without `dbname`.
+pub fn parse_file_name(filename: &str) -> Option<(u64, FileType)> {
+    match filename {
+        "CURRENT" => Some((0, FileType::CurrentFile)),
+        "LOCK" => Some((0, FileType::DBLockFile)),
+        "LOG" | "LOG.old" => Some((0, FileType::InfoLogFile)),
+        _ => {
+            if let Some(rest) = filename.strip_prefix("MANIFEST-") {
+                let (number, rest) = consume_decimal_number(rest)?;
+                if !rest.is_empty() {
+                    return None;
+                }
+                return Some((number, FileType::DescriptorFile));
+            }
+
+            let (number, suffix) = consume_decimal_number(filename)?;
+            let file_type = match suffix {
+                ".log" => FileType::LogFile,
+                ".sst" | ".ldb" => FileType::TableFile,
+                ".dbtmp" => FileType::TempFile,
+                _ => return None,
+            };
+            Some((number, file_type))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        // Successful parses
+        let cases: [(&str, u64, FileType); 15] = [
+            ("100.log", 100, FileType::LogFile),
+            ("0.log", 0, FileType::LogFile),
+            ("0.sst", 0, FileType::TableFile),
+            ("0.ldb", 0, FileType::TableFile),
+            ("CURRENT", 0, FileType::CurrentFile),
+            ("LOCK", 0, FileType::DBLockFile),
+            ("MANIFEST-2", 2, FileType::DescriptorFile),
+            ("MANIFEST-7", 7, FileType::DescriptorFile),
+            ("LOG", 0, FileType::InfoLogFile),
+            ("LOG.old", 0, FileType::InfoLogFile),
+            ("18446744073709551615.log", 18446744073709551615, FileType::LogFile),
+            ("123.dbtmp", 123, FileType::TempFile),
+            ("000123.ldb", 123, FileType::TableFile),
+            ("MANIFEST-000005", 5, FileType::DescriptorFile),
+            ("7.sst", 7, FileType::TableFile),
+        ];
+        for (name, number, file_type) in cases.iter() {
+            assert_eq!(parse_file_name(name), Some((*number, *file_type)), "{}", name);
+        }
+
+        // Errors
+        let errors = [
+            "",
+            "foo",
+            "foo-dx-100.log",
+            ".log",
+            "",
+            "manifest",
+            "CURREN",
+            "CURRENTX",
+            "MANIFES",
+            "MANIFEST",
+            "MANIFEST-",
+            "XMANIFEST-3",
+            "MANIFEST-3x",
+            "LOC",
+            "LOCKx",
+            "LO",
+            "LOGx",
+            "18446744073709551616.log",
+            "184467440737095516150.log",
+            "100",
+            "100.",
+            "100.lop",
+        ];
+        for name in errors.iter() {
+            assert_eq!(parse_file_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn construction() {
+        let check = |fname: &str, prefix: &str, number: u64, file_type: FileType| {
+            assert!(fname.starts_with(prefix), "{}", fname);
+            assert_eq!(
+                parse_file_name(&fname[prefix.len()..]),
+                Some((number, file_type)),
+                "{}",
+                fname
+            );
+        };
+
+        check(&current_file_name("foo"), "foo/", 0, FileType::CurrentFile);
+        check(&lock_file_name("foo"), "foo/", 0, FileType::DBLockFile);
+        check(&log_file_name("foo", 192), "foo/", 192, FileType::LogFile);
+        check(&table_file_name("bar", 200), "bar/", 200, FileType::TableFile);
+        check(&sst_table_file_name("bar", 200), "bar/", 200, FileType::TableFile);
+        check(&descriptor_file_name("bar", 100), "bar/", 100, FileType::DescriptorFile);
+        check(&temp_file_name("tmp", 999), "tmp/", 999, FileType::TempFile);
+        check(&info_log_file_name("foo"), "foo/", 0, FileType::InfoLogFile);
+        check(&old_info_log_file_name("foo"), "foo/", 0, FileType::InfoLogFile);
+    }
+}
@@ -0,0 +1,6 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+pub mod db_impl;
+pub mod filename;
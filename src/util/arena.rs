@@ -2,145 +2,375 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-use std::ptr;
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
 use std::mem;
+use std::ptr::{self, NonNull};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::slice;
 
 const BLOCK_SIZE: usize = 4096;
 
+// Alignment of every block allocated by the arena. Any allocation whose
+// alignment is at most this can be served from the start of a fresh block.
+const BLOCK_ALIGN: usize = 16;
+
+/// Options to control the behavior of an `Arena`.
+#[derive(Clone, Debug)]
+pub struct ArenaOptions {
+    /// Size of the blocks that small allocations are carved from. A
+    /// memtable typically wants this to be a fraction of its write buffer
+    /// size (e.g. write_buffer_size / 8).
+    ///
+    /// Default: 4KB
+    pub block_size: usize,
+
+    /// If true, every new or reused block is filled with zeros. Callers that
+    /// always initialize what they allocate can turn this off to avoid
+    /// touching every page up front.
+    ///
+    /// Default: true
+    pub zero_fill: bool,
+
+    /// If non-zero, blocks of at least this size are mapped with mmap on
+    /// Linux, first with MAP_HUGETLB and otherwise as regular pages with
+    /// transparent huge pages requested through madvise. The mapping is
+    /// rounded up to a multiple of this size. Falls back to the heap if
+    /// mapping fails, and is ignored on other platforms.
+    ///
+    /// Default: 0
+    pub huge_page_size: usize,
+}
+
+impl Default for ArenaOptions {
+    fn default() -> Self {
+        Self {
+            block_size: BLOCK_SIZE,
+            zero_fill: true,
+            huge_page_size: 0,
+        }
+    }
+}
+
 /// Similar to "Arena" in leveldb C++.
-/// Note that even though methods of this require receiver to be unique self
-/// reference, the returned values are raw pointers, and thus do not "hold" the
-/// self reference. We could change the receiver type to shared reference.
-/// However, it makes the code more complex and may require more `try_borrow`
-/// calls, which carry certain costs.
-/// Therefore, the suggested way is to create `ArenaRef` and use interior
-/// mutability.
-pub type ArenaRef = Rc<RefCell<Arena>>;
+/// All allocation methods take a shared receiver and keep their state in
+/// cells, so typed allocations such as `alloc` can hand out references whose
+/// lifetime is tied to the arena while further allocations are made. Only
+/// `reset` requires a unique reference, which guarantees that no references
+/// into the arena are still alive.
+/// The suggested way to share an arena between several owners on one thread
+/// is `ArenaRef`. See `ConcurrentArena` for an arena that can be shared
+/// between threads.
+pub type ArenaRef = Rc<Arena>;
 
 pub struct Arena {
+    options: ArenaOptions,
+
     // Allocation state
-    alloc_ptr: *mut u8,
-    alloc_bytes_remaining: usize,
+    alloc_ptr: Cell<*mut u8>,
+    alloc_bytes_remaining: Cell<usize>,
 
     // Vector of new allocated memory blocks
-    blocks: Vec<Vec<u8>>,
+    blocks: RefCell<Vec<Block>>,
+
+    // Blocks of `options.block_size` kept by `reset` for reuse
+    free_blocks: RefCell<Vec<Block>>,
 
-    // Total memory usage of the arena.
+    // Total memory allocated by the arena, including blocks kept for reuse.
     //
-    // TODO: This member is accessed via atomics, but the others are accessed without any locking.
-    //       Is this OK?
-    memory_usage: usize,
+    // Like the allocation state above, this is never shared between threads,
+    // so it needs no atomics. `ConcurrentArena` reads it under its own lock.
+    memory_allocated: Cell<usize>,
 }
 
+// The arena exclusively owns every block its pointers refer to, so it is safe
+// to move it to another thread.
+unsafe impl Send for Arena {}
+
 impl Arena {
     pub fn new() -> Self {
+        Self::with_options(ArenaOptions::default())
+    }
+
+    /// Return an arena that allocates blocks as described by `options`.
+    ///
+    /// Panic if `options.block_size` is zero.
+    pub fn with_options(options: ArenaOptions) -> Self {
+        assert!(options.block_size > 0);
         Self {
-            alloc_ptr: ptr::null_mut(),
-            alloc_bytes_remaining: 0,
-            blocks: Vec::new(),
-            memory_usage: 0,
+            options,
+            alloc_ptr: Cell::new(ptr::null_mut()),
+            alloc_bytes_remaining: Cell::new(0),
+            blocks: RefCell::new(Vec::new()),
+            free_blocks: RefCell::new(Vec::new()),
+            memory_allocated: Cell::new(0),
         }
     }
 
+    /// Return the options this arena was created with.
+    pub fn options(&self) -> &ArenaOptions {
+        &self.options
+    }
+
     /// Return a pointer to a newly byte slice with length `bytes`.
     #[inline]
-    pub fn allocate(&mut self, bytes: usize) -> *mut u8 {
+    pub fn allocate(&self, bytes: usize) -> *mut u8 {
         // The semantics of what to return are a bit messy if we allow
         // 0-byte allocations, so we disallow them here (we don't need
         // them for our internal use).
         assert!(bytes > 0);
-        if bytes <= self.alloc_bytes_remaining {
-            let result = self.alloc_ptr;
-            unsafe {
-                self.alloc_ptr = self.alloc_ptr.offset(bytes as isize);
-                self.alloc_bytes_remaining -= bytes;
-            }
+        let remaining = self.alloc_bytes_remaining.get();
+        if bytes <= remaining {
+            let result = self.alloc_ptr.get();
+            self.alloc_ptr.set(result.wrapping_add(bytes));
+            self.alloc_bytes_remaining.set(remaining - bytes);
             result
         } else {
-            self.allocate_fallback(bytes)
+            self.allocate_fallback(bytes, 1)
         }
     }
 
     /// Return a pointer aligned to a newly byte slice with length `bytes`.
-    pub fn allocate_aligned(&mut self, bytes: usize) -> *mut u8 {
+    /// The alignment is the pointer size, but at least 8.
+    pub fn allocate_aligned(&self, bytes: usize) -> *mut u8 {
         let ptr_size = mem::size_of::<usize>();
-        let aligns = if ptr_size > 8 {
-            ptr_size
-        } else {
-            8
-        };
-        // Pointer size should be a power of 2.
-        assert_eq!((aligns & (aligns - 1)), 0);
+        let aligns = if ptr_size > 8 { ptr_size } else { 8 };
+        self.allocate_with_align(bytes, aligns)
+    }
 
-        let current_mode = (self.alloc_ptr as usize) & (aligns - 1);
-        let slop = if current_mode == 0 {
-            0
-        } else {
-            aligns - current_mode
-        };
+    /// Return a pointer to a newly byte slice with length `bytes`, aligned to
+    /// `align` bytes.
+    ///
+    /// Panic if `align` is not a power of 2.
+    pub fn allocate_with_align(&self, bytes: usize, align: usize) -> *mut u8 {
+        assert!(bytes > 0);
+        assert!(align.is_power_of_two());
+
+        let alloc_ptr = self.alloc_ptr.get();
+        let current_mod = (alloc_ptr as usize) & (align - 1);
+        let slop = if current_mod == 0 { 0 } else { align - current_mod };
         let needed = bytes + slop;
 
-        let result = if needed <= self.alloc_bytes_remaining {
-            unsafe {
-                let tmp = self.alloc_ptr.offset(slop as isize);
-                self.alloc_ptr = self.alloc_ptr.offset(needed as isize);
-                self.alloc_bytes_remaining -= needed;
-                tmp
-            }
+        let remaining = self.alloc_bytes_remaining.get();
+        let result = if needed <= remaining {
+            let result = alloc_ptr.wrapping_add(slop);
+            self.alloc_ptr.set(alloc_ptr.wrapping_add(needed));
+            self.alloc_bytes_remaining.set(remaining - needed);
+            result
         } else {
             // allocate_fallback always returned aligned memory
-            self.allocate_fallback(bytes)
+            self.allocate_fallback(bytes, align)
         };
 
-        assert_eq!((result as usize) & (aligns - 1), 0);
+        assert_eq!((result as usize) & (align - 1), 0);
         result
     }
 
+    /// Move `value` into the arena and return a reference to it.
+    ///
+    /// The value lives as long as the arena, but its destructor is never run.
+    pub fn alloc<T>(&self, value: T) -> &T {
+        let size = mem::size_of::<T>();
+        let ptr = if size == 0 {
+            NonNull::<T>::dangling().as_ptr()
+        } else {
+            self.allocate_with_align(size, mem::align_of::<T>()) as *mut T
+        };
+        unsafe {
+            ptr.write(value);
+            &*ptr
+        }
+    }
+
+    /// Copy `src` into the arena and return a reference to the copy.
+    pub fn alloc_slice_copy(&self, src: &[u8]) -> &[u8] {
+        if src.is_empty() {
+            return &[];
+        }
+        let ptr = self.allocate(src.len());
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            slice::from_raw_parts(ptr, src.len())
+        }
+    }
+
     /// Returns an estimate of the total memory usage of data allocated by the arena.
+    ///
+    /// Unlike `memory_allocated`, this doesn't count the unused tail of the
+    /// current block or blocks kept by `reset` that haven't been reused yet.
     pub fn memory_usage(&self) -> usize {
-        self.memory_usage
+        let free_bytes: usize = self.free_blocks.borrow().iter().map(|b| b.charge()).sum();
+        self.memory_allocated() - free_bytes - self.alloc_bytes_remaining.get()
+    }
+
+    /// Returns the total memory the arena has obtained from the system.
+    pub fn memory_allocated(&self) -> usize {
+        self.memory_allocated.get()
     }
 
-    /// TODO
-    fn allocate_fallback(&mut self, bytes: usize) -> *mut u8 {
-        if bytes > BLOCK_SIZE / 4 {
-            // Object is more than a quarter of our block size.
+    /// Discard everything allocated so far. Blocks of the standard size are
+    /// kept and reused by later allocations; larger blocks are freed.
+    pub fn reset(&mut self) {
+        let block_size = self.options.block_size;
+        let blocks = mem::take(self.blocks.get_mut());
+        let free_blocks = self.free_blocks.get_mut();
+        free_blocks.extend(blocks.into_iter().filter(|b| b.size == block_size));
+
+        let retained = free_blocks.iter().map(|b| b.charge()).sum();
+        self.memory_allocated.set(retained);
+        self.alloc_ptr.set(ptr::null_mut());
+        self.alloc_bytes_remaining.set(0);
+    }
+
+    fn allocate_fallback(&self, bytes: usize, align: usize) -> *mut u8 {
+        let block_size = self.options.block_size;
+        if bytes > block_size / 4 || align > BLOCK_ALIGN {
+            // Object is more than a quarter of our block size, or needs more
+            // alignment than a block provides.
             // Allocate it separately to avoid wasting too much space in leftover bytes.
-            return self.allocate_new_block(bytes);
+            return self.allocate_new_block(bytes, align.max(BLOCK_ALIGN));
         }
 
         // We waste the remaining space in the current block.
-        self.alloc_ptr = self.allocate_new_block(BLOCK_SIZE);
-        self.alloc_bytes_remaining = BLOCK_SIZE;
+        let result = self.allocate_new_block(block_size, BLOCK_ALIGN);
+        self.alloc_ptr.set(result.wrapping_add(bytes));
+        self.alloc_bytes_remaining.set(block_size - bytes);
+        result
+    }
 
-        let result = self.alloc_ptr;
-        unsafe {
-            self.alloc_ptr = self.alloc_ptr.offset(bytes as isize);
-            self.alloc_bytes_remaining -= bytes;
-        }
+    fn allocate_new_block(&self, block_bytes: usize, align: usize) -> *mut u8 {
+        let reused = if block_bytes == self.options.block_size && align == BLOCK_ALIGN {
+            self.free_blocks.borrow_mut().pop()
+        } else {
+            None
+        };
+        let block = match reused {
+            Some(block) => {
+                if self.options.zero_fill {
+                    unsafe { ptr::write_bytes(block.ptr.as_ptr(), 0, block_bytes) };
+                }
+                block
+            }
+            None => {
+                let block = Block::new(block_bytes, align, &self.options);
+                self.memory_allocated.set(self.memory_allocated.get() + block.charge());
+                block
+            }
+        };
+
+        let result = block.ptr.as_ptr();
+        self.blocks.borrow_mut().push(block);
         result
     }
+}
 
-    fn allocate_new_block(&mut self, block_bytes: usize) -> *mut u8 {
-        let mut buf: Vec<u8> = Vec::with_capacity(block_bytes);
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A memory block owned by an `Arena`.
+struct Block {
+    ptr: NonNull<u8>,
+    // Usable size of the block
+    size: usize,
+    source: BlockSource,
+}
+
+enum BlockSource {
+    Heap(Layout),
+    // Length of the mapping, which may exceed `size`
+    #[cfg(target_os = "linux")]
+    Mmap(usize),
+}
+
+impl Block {
+    fn new(size: usize, align: usize, options: &ArenaOptions) -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            if options.huge_page_size > 0 && size >= options.huge_page_size {
+                if let Some(block) = Self::new_mmap(size, align, options.huge_page_size) {
+                    return block;
+                }
+            }
+        }
+
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe {
+            if options.zero_fill {
+                alloc::alloc_zeroed(layout)
+            } else {
+                alloc::alloc(layout)
+            }
+        };
+        match NonNull::new(ptr) {
+            Some(ptr) => Self {
+                ptr,
+                size,
+                source: BlockSource::Heap(layout),
+            },
+            None => alloc::handle_alloc_error(layout),
+        }
+    }
+
+    /// Map an anonymous, zero-filled block backed by huge pages if possible.
+    #[cfg(target_os = "linux")]
+    fn new_mmap(size: usize, align: usize, huge_page_size: usize) -> Option<Self> {
+        let len = size.checked_next_multiple_of(huge_page_size)?;
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
         unsafe {
-            buf.set_len(block_bytes);
-            ptr::write_bytes(buf.as_mut_ptr(), 0, block_bytes);
+            let mut addr = libc::mmap(ptr::null_mut(), len, prot, flags | libc::MAP_HUGETLB, -1, 0);
+            if addr == libc::MAP_FAILED {
+                // No reserved huge pages; ask for transparent huge pages instead.
+                addr = libc::mmap(ptr::null_mut(), len, prot, flags, -1, 0);
+                if addr == libc::MAP_FAILED {
+                    return None;
+                }
+                // Only a hint, so ignore failures.
+                let _ = libc::madvise(addr, len, libc::MADV_HUGEPAGE);
+            }
+            // Mappings are page aligned, which covers every alignment we hand out
+            // in practice. Fall back to the heap for anything stricter.
+            if (addr as usize) & (align - 1) != 0 {
+                libc::munmap(addr, len);
+                return None;
+            }
+            Some(Self {
+                ptr: NonNull::new_unchecked(addr as *mut u8),
+                size,
+                source: BlockSource::Mmap(len),
+            })
         }
+    }
 
-        let result = buf.as_mut_ptr();
-        self.blocks.push(buf);
-        self.memory_usage = self.memory_usage + block_bytes + mem::size_of::<usize>();
+    /// Number of bytes charged to the arena for this block.
+    fn charge(&self) -> usize {
+        let len = match self.source {
+            BlockSource::Heap(layout) => layout.size(),
+            #[cfg(target_os = "linux")]
+            BlockSource::Mmap(len) => len,
+        };
+        len + mem::size_of::<usize>()
+    }
+}
 
-        result
+impl Drop for Block {
+    fn drop(&mut self) {
+        match self.source {
+            BlockSource::Heap(layout) => unsafe { alloc::dealloc(self.ptr.as_ptr(), layout) },
+            #[cfg(target_os = "linux")]
+            BlockSource::Mmap(len) => unsafe {
+                libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, len);
+            },
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Arena;
+    use super::{Arena, ArenaOptions};
     use crate::util::random::Random;
     use std::slice;
     use crate::util::arena::BLOCK_SIZE;
@@ -148,28 +378,141 @@ mod tests {
     #[test]
     fn empty() {
         let arena = Arena::new();
-        assert!(arena.alloc_ptr.is_null());
-        assert_eq!(arena.alloc_bytes_remaining, 0);
+        assert!(arena.alloc_ptr.get().is_null());
+        assert_eq!(arena.alloc_bytes_remaining.get(), 0);
         assert_eq!(arena.memory_usage(), 0);
     }
 
     #[test]
     fn aligned() {
-        let mut arena = Arena::new();
+        let arena = Arena::new();
         let ptr_size = std::mem::size_of::<usize>();
         assert!(ptr_size > 1);
 
-        let _ = arena.allocate_fallback(1);
+        let _ = arena.allocate_fallback(1, 1);
         let _ = arena.allocate_aligned(512);
-        assert_eq!(arena.alloc_ptr.is_null(), false);
-        assert_eq!(arena.alloc_bytes_remaining, BLOCK_SIZE - 512 - ptr_size);
+        assert_eq!(arena.alloc_ptr.get().is_null(), false);
+        assert_eq!(arena.alloc_bytes_remaining.get(), BLOCK_SIZE - 512 - ptr_size);
         // assert_eq!(arena.memory_usage(), 0);
     }
 
+    #[test]
+    fn arbitrary_alignment() {
+        let arena = Arena::new();
+        for &align in [1, 2, 4, 8, 16, 32, 64, 4096, 8192].iter() {
+            let _ = arena.allocate(1);
+            let r = arena.allocate_with_align(3, align);
+            assert_eq!((r as usize) % align, 0);
+        }
+    }
+
+    #[test]
+    fn typed() {
+        #[derive(Debug, PartialEq)]
+        struct Node {
+            key: u64,
+            height: u8,
+        }
+
+        let arena = Arena::new();
+        let _ = arena.allocate(1);
+        let a = arena.alloc(Node { key: 7, height: 3 });
+        let b = arena.alloc(0x1234u16);
+        let s = arena.alloc_slice_copy(b"hello");
+        let unit = arena.alloc(());
+
+        assert_eq!(*a, Node { key: 7, height: 3 });
+        assert_eq!((a as *const Node as usize) % std::mem::align_of::<Node>(), 0);
+        assert_eq!(*b, 0x1234);
+        assert_eq!(s, b"hello");
+        assert_eq!(*unit, ());
+        assert!(arena.alloc_slice_copy(&[]).is_empty());
+    }
+
+    #[test]
+    fn reset() {
+        let mut arena = Arena::new();
+        for _ in 0..10 {
+            let _ = arena.allocate(BLOCK_SIZE / 4);
+        }
+        let _ = arena.allocate(BLOCK_SIZE * 2);
+        let allocated = arena.memory_allocated();
+        assert!(allocated > BLOCK_SIZE * 4);
+        assert!(arena.memory_usage() <= allocated);
+
+        arena.reset();
+        assert!(arena.alloc_ptr.get().is_null());
+        assert_eq!(arena.memory_usage(), 0);
+        let retained = arena.memory_allocated();
+        assert!(retained > 0 && retained < allocated);
+
+        // Reused blocks are not counted again, and are zero-filled.
+        for _ in 0..10 {
+            let r = arena.allocate(BLOCK_SIZE / 4);
+            assert!(unsafe { slice::from_raw_parts(r, BLOCK_SIZE / 4) }.iter().all(|b| *b == 0));
+        }
+        assert_eq!(arena.memory_allocated(), retained);
+        assert!(arena.memory_usage() >= BLOCK_SIZE / 4 * 10);
+    }
+
+    #[test]
+    fn block_size() {
+        let arena = Arena::with_options(ArenaOptions {
+            block_size: 64 << 10,
+            zero_fill: false,
+            ..Default::default()
+        });
+        let _ = arena.allocate(100);
+        assert_eq!(arena.alloc_bytes_remaining.get(), (64 << 10) - 100);
+        assert_eq!(arena.memory_usage(), 100 + std::mem::size_of::<usize>());
+        assert_eq!(arena.memory_allocated(), (64 << 10) + std::mem::size_of::<usize>());
+
+        // Larger than a quarter of the block size: allocated separately.
+        let _ = arena.allocate(64 << 10);
+        assert_eq!(arena.alloc_bytes_remaining.get(), (64 << 10) - 100);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn huge_page() {
+        use super::BlockSource;
+
+        const HUGE_PAGE_SIZE: usize = 2 << 20;
+        let arena = Arena::with_options(ArenaOptions {
+            block_size: HUGE_PAGE_SIZE,
+            huge_page_size: HUGE_PAGE_SIZE,
+            ..Default::default()
+        });
+        let r = arena.allocate_aligned(1000);
+        let data = unsafe { slice::from_raw_parts_mut(r, 1000) };
+        assert!(data.iter().all(|b| *b == 0));
+        data.fill(0xab);
+
+        let _ = arena.allocate(HUGE_PAGE_SIZE + 1);
+        assert!(data.iter().all(|b| *b == 0xab));
+
+        // Mappings are rounded up to whole huge pages. If mmap is unavailable
+        // the arena falls back to the heap, which charges the exact size.
+        let blocks = arena.blocks.borrow();
+        assert_eq!(blocks.len(), 2);
+        let mut expected = 0;
+        for (block, size) in blocks.iter().zip([HUGE_PAGE_SIZE, HUGE_PAGE_SIZE + 1].iter()) {
+            assert_eq!(block.size, *size);
+            expected += match block.source {
+                BlockSource::Mmap(len) => {
+                    assert_eq!(len, size.next_multiple_of(HUGE_PAGE_SIZE));
+                    len
+                }
+                BlockSource::Heap(_) => *size,
+            } + std::mem::size_of::<usize>();
+        }
+        assert_eq!(arena.memory_allocated(), expected);
+    }
+
     #[test]
     fn simple() {
         const N: u32 = 100000;
-        let mut arena = Arena::new();
+        let arena = Arena::new();
         let rnd = Random::new(301);
         let mut bytes: usize = 0;
 
@@ -217,4 +560,4 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,114 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use crate::util::crc32c;
+use crate::util::xxhash;
+
+// Multiplier used to fold the block type byte into 64-bit hash checksums.
+const LAST_BYTE_PRIME: u32 = 0x6b9083d9;
+
+/// The checksum used to protect the blocks of a table. The type is recorded
+/// once per table, so a reader can verify any table regardless of which
+/// checksum it was written with. Tables without a recorded type use
+/// `ChecksumType::Crc32c`, the only checksum the original format knows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumType {
+    #[default]
+    Crc32c = 1,
+    XxHash64 = 2,
+    Xxh3 = 3,
+}
+
+impl ChecksumType {
+    /// Return the checksum type stored as `value`, or `None` if it is unknown.
+    pub fn from_u8(value: u8) -> Option<ChecksumType> {
+        match value {
+            1 => Some(ChecksumType::Crc32c),
+            2 => Some(ChecksumType::XxHash64),
+            3 => Some(ChecksumType::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// Return the value used to record this checksum type on disk.
+    #[inline]
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Return the checksum of a block: `contents` followed by the one byte
+/// `block_type` that is stored next to it in the block trailer.
+///
+/// For `ChecksumType::Crc32c` this is the masked crc32c of both, exactly as
+/// leveldb computes it, so existing tables keep verifying. The 64-bit hashes
+/// are truncated to their lower 32 bits and the type byte is folded in
+/// afterwards, which avoids hashing a concatenated copy of the block.
+pub fn block_checksum(checksum_type: ChecksumType, contents: &[u8], block_type: u8) -> u32 {
+    match checksum_type {
+        ChecksumType::Crc32c => crc32c::mask(crc32c::extend(crc32c::value(contents), &[block_type])),
+        ChecksumType::XxHash64 => fold_last_byte(xxhash::xxh64(contents, 0), block_type),
+        ChecksumType::Xxh3 => fold_last_byte(xxhash::xxh3_64(contents), block_type),
+    }
+}
+
+/// Return true iff `expected` is the checksum of `contents` and `block_type`
+/// under `checksum_type`.
+#[inline]
+pub fn verify_block_checksum(
+    checksum_type: ChecksumType,
+    contents: &[u8],
+    block_type: u8,
+    expected: u32,
+) -> bool {
+    block_checksum(checksum_type, contents, block_type) == expected
+}
+
+#[inline]
+fn fold_last_byte(hash: u64, last_byte: u8) -> u32 {
+    (hash as u32) ^ (last_byte as u32).wrapping_mul(LAST_BYTE_PRIME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ChecksumType; 3] = [ChecksumType::Crc32c, ChecksumType::XxHash64, ChecksumType::Xxh3];
+
+    #[test]
+    fn types() {
+        for t in ALL.iter() {
+            assert_eq!(ChecksumType::from_u8(t.to_u8()), Some(*t));
+        }
+        assert_eq!(ChecksumType::from_u8(0), None);
+        assert_eq!(ChecksumType::from_u8(4), None);
+        assert_eq!(ChecksumType::default(), ChecksumType::Crc32c);
+    }
+
+    #[test]
+    fn crc32c_compatible() {
+        let mut block = b"some block contents".to_vec();
+        block.push(1);
+        assert_eq!(
+            block_checksum(ChecksumType::Crc32c, &block[..block.len() - 1], 1),
+            crc32c::mask(crc32c::value(&block))
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let contents = b"some block contents";
+        for t in ALL.iter() {
+            let checksum = block_checksum(*t, contents, 0);
+            assert!(verify_block_checksum(*t, contents, 0, checksum));
+            assert!(!verify_block_checksum(*t, contents, 1, checksum));
+            assert!(!verify_block_checksum(*t, b"some block content", 0, checksum));
+            assert!(!verify_block_checksum(*t, b"some block contentz", 0, checksum));
+        }
+        assert_ne!(
+            block_checksum(ChecksumType::XxHash64, contents, 0),
+            block_checksum(ChecksumType::Xxh3, contents, 0)
+        );
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::util::arena::{Arena, ArenaOptions};
+
+// Upper bound on the size of the chunks that shards carve out of the
+// underlying arena.
+const MAX_SHARD_BLOCK_SIZE: usize = 128 << 10;
+
+/// An arena that can be shared between threads, similar to
+/// "ConcurrentArena" in rocksdb.
+///
+/// Small allocations are served from per-thread shards, each of which bump
+/// allocates out of a chunk it reserved from an underlying `Arena`. Threads
+/// therefore only contend on the shared arena when a shard runs out of space
+/// or for large allocations. `memory_usage()` is an atomic load and can be
+/// called from any thread without taking a lock.
+///
+/// Like `Arena`, returned pointers stay valid until the arena is dropped.
+pub struct ConcurrentArena {
+    // Size of the chunks that shards carve out of the underlying arena.
+    // Requests larger than a quarter of this bypass the shards and go straight
+    // to the arena.
+    shard_block_size: usize,
+    shards: Box<[Mutex<Shard>]>,
+    arena: Mutex<Arena>,
+
+    // Mirrors of the underlying arena's counters, readable without the lock
+    memory_usage: AtomicUsize,
+    memory_allocated: AtomicUsize,
+}
+
+struct Shard {
+    alloc_ptr: *mut u8,
+    alloc_bytes_remaining: usize,
+}
+
+// A shard only points into blocks owned by the arena it belongs to.
+unsafe impl Send for Shard {}
+
+impl ConcurrentArena {
+    pub fn new() -> Self {
+        Self::with_options(ArenaOptions::default())
+    }
+
+    /// Return an arena whose underlying `Arena` is configured by `options`.
+    pub fn with_options(options: ArenaOptions) -> Self {
+        let shard_block_size = (options.block_size / 8).clamp(1, MAX_SHARD_BLOCK_SIZE);
+        let num_shards = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .next_power_of_two();
+        let shards = (0..num_shards)
+            .map(|_| {
+                Mutex::new(Shard {
+                    alloc_ptr: ptr::null_mut(),
+                    alloc_bytes_remaining: 0,
+                })
+            })
+            .collect();
+        Self {
+            shard_block_size,
+            shards,
+            arena: Mutex::new(Arena::with_options(options)),
+            memory_usage: AtomicUsize::new(0),
+            memory_allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Return a pointer to a newly byte slice with length `bytes`.
+    #[inline]
+    pub fn allocate(&self, bytes: usize) -> *mut u8 {
+        self.allocate_impl(bytes, false)
+    }
+
+    /// Return a pointer aligned to a newly byte slice with length `bytes`.
+    /// The alignment is the same as `Arena::allocate_aligned`.
+    #[inline]
+    pub fn allocate_aligned(&self, bytes: usize) -> *mut u8 {
+        self.allocate_impl(bytes, true)
+    }
+
+    /// Returns an estimate of the total memory usage of data allocated by the arena.
+    /// Space reserved by a shard counts as used even before it is handed out.
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total memory the arena has obtained from the system.
+    #[inline]
+    pub fn memory_allocated(&self) -> usize {
+        self.memory_allocated.load(Ordering::Relaxed)
+    }
+
+    fn allocate_impl(&self, bytes: usize, aligned: bool) -> *mut u8 {
+        assert!(bytes > 0);
+        if bytes > self.shard_block_size / 4 {
+            // Object is too large to be worth caching in a shard.
+            return self.arena_allocate(|arena| {
+                if aligned {
+                    arena.allocate_aligned(bytes)
+                } else {
+                    arena.allocate(bytes)
+                }
+            });
+        }
+
+        let mut shard = self.lock_shard();
+        let slop = if aligned {
+            let aligns = align_of_aligned();
+            let current_mod = (shard.alloc_ptr as usize) & (aligns - 1);
+            if current_mod == 0 {
+                0
+            } else {
+                aligns - current_mod
+            }
+        } else {
+            0
+        };
+
+        if bytes + slop > shard.alloc_bytes_remaining {
+            // We waste the remaining space in the shard's current chunk.
+            // Chunks are always aligned, so no slop is needed.
+            let shard_block_size = self.shard_block_size;
+            shard.alloc_ptr = self.arena_allocate(|arena| arena.allocate_aligned(shard_block_size));
+            shard.alloc_bytes_remaining = shard_block_size;
+            let result = shard.alloc_ptr;
+            shard.alloc_ptr = result.wrapping_add(bytes);
+            shard.alloc_bytes_remaining -= bytes;
+            return result;
+        }
+
+        let result = shard.alloc_ptr.wrapping_add(slop);
+        shard.alloc_ptr = result.wrapping_add(bytes);
+        shard.alloc_bytes_remaining -= bytes + slop;
+        result
+    }
+
+    fn arena_allocate<F: FnOnce(&mut Arena) -> *mut u8>(&self, f: F) -> *mut u8 {
+        let mut arena = self.arena.lock().unwrap();
+        let result = f(&mut arena);
+        self.memory_usage.store(arena.memory_usage(), Ordering::Relaxed);
+        self.memory_allocated.store(arena.memory_allocated(), Ordering::Relaxed);
+        result
+    }
+
+    /// Lock the shard for the calling thread. If it is held by another thread,
+    /// try the other shards before waiting on it.
+    fn lock_shard(&self) -> std::sync::MutexGuard<'_, Shard> {
+        let mask = self.shards.len() - 1;
+        let hint = shard_hint();
+        for i in 0..self.shards.len() {
+            if let Ok(shard) = self.shards[(hint + i) & mask].try_lock() {
+                return shard;
+            }
+        }
+        self.shards[hint & mask].lock().unwrap()
+    }
+}
+
+impl Default for ConcurrentArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Alignment used by `allocate_aligned`, mirroring `Arena::allocate_aligned`.
+#[inline]
+fn align_of_aligned() -> usize {
+    std::mem::size_of::<usize>().max(8)
+}
+
+/// Return a per-thread index used to spread threads across shards.
+fn shard_hint() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static HINT: Cell<usize> = Cell::new(NEXT.fetch_add(1, Ordering::Relaxed));
+    }
+    HINT.with(|hint| hint.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConcurrentArena;
+    use crate::util::arena::ArenaOptions;
+    use crate::util::random::Random;
+    use std::slice;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ConcurrentArena>();
+    }
+
+    #[test]
+    fn empty() {
+        let arena = ConcurrentArena::new();
+        assert_eq!(arena.memory_usage(), 0);
+        assert_eq!(arena.memory_allocated(), 0);
+    }
+
+    #[test]
+    fn block_size() {
+        let arena = ConcurrentArena::with_options(ArenaOptions {
+            block_size: 1 << 20,
+            ..Default::default()
+        });
+        assert_eq!(arena.shard_block_size, 128 << 10);
+        let _ = arena.allocate(1000);
+        assert!(arena.memory_usage() >= 128 << 10);
+        assert!(arena.memory_allocated() >= 1 << 20);
+    }
+
+    #[test]
+    fn concurrent() {
+        const THREADS: u32 = 4;
+        const N: u32 = 20000;
+        let arena = Arc::new(ConcurrentArena::new());
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let arena = arena.clone();
+                thread::spawn(move || {
+                    let rnd = Random::new(301 + t);
+                    let mut allocated = Vec::with_capacity(N as usize);
+                    let mut bytes = 0;
+                    for i in 0..N {
+                        let s = if rnd.one_in(1000) {
+                            rnd.uniform(3000)
+                        } else {
+                            rnd.uniform(20)
+                        } as usize
+                            + 1;
+                        let aligned = rnd.one_in(10);
+                        let r = if aligned {
+                            arena.allocate_aligned(s)
+                        } else {
+                            arena.allocate(s)
+                        };
+                        if aligned {
+                            assert_eq!((r as usize) % super::align_of_aligned(), 0);
+                        }
+                        let pattern = (i as u8) ^ (t as u8);
+                        unsafe { slice::from_raw_parts_mut(r, s) }.fill(pattern);
+                        allocated.push((r as usize, s, pattern));
+                        bytes += s;
+                    }
+                    for (r, s, pattern) in allocated {
+                        let data = unsafe { slice::from_raw_parts(r as *const u8, s) };
+                        assert!(data.iter().all(|b| *b == pattern));
+                    }
+                    bytes
+                })
+            })
+            .collect();
+
+        let bytes: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert!(arena.memory_usage() >= bytes);
+    }
+}
@@ -2,14 +2,193 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-extern crate crc;
+use crate::util::coding::decode_fixed_64;
 
 const MASK_DELTA: u32 = 0xa282ead8;
 
+// Castagnoli polynomial, bit-reversed.
+const POLY: u32 = 0x82f63b78;
+
+// TABLES[0] is the classic byte-at-a-time table. TABLES[k][b] is the crc of
+// byte `b` followed by `k` zero bytes, which lets the portable path fold in
+// eight bytes per iteration ("slicing-by-8").
+static TABLES: [[u32; 256]; 8] = make_tables();
+
+// The accelerated path splits long inputs into three streams of this many
+// bytes, crcs them in parallel and folds the results together.
+#[cfg(target_arch = "x86_64")]
+const STREAM_LEN: usize = 256;
+
+// Constants that shift a crc past one and two streams of zero bytes with a
+// carry-less multiply. See `extend_sse42_pclmul`.
+#[cfg(target_arch = "x86_64")]
+const SHIFT_ONE_STREAM: u64 = x_pow_mod_p(STREAM_LEN as u64 * 8 - 33) as u64;
+#[cfg(target_arch = "x86_64")]
+const SHIFT_TWO_STREAMS: u64 = x_pow_mod_p(STREAM_LEN as u64 * 16 - 33) as u64;
+
+const fn make_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            j += 1;
+        }
+        tables[0][i] = crc;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = tables[k - 1][i];
+            tables[k][i] = (prev >> 8) ^ tables[0][(prev & 0xff) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+/// Return a*b modulo the crc polynomial, where a and b are bit-reversed
+/// polynomials like the crc itself (x^0 is the top bit).
+#[cfg(target_arch = "x86_64")]
+const fn mul_mod_p(a: u32, mut b: u32) -> u32 {
+    let mut m = 1u32 << 31;
+    let mut p = 0;
+    while m != 0 {
+        if a & m != 0 {
+            p ^= b;
+        }
+        b = if b & 1 != 0 { (b >> 1) ^ POLY } else { b >> 1 };
+        m >>= 1;
+    }
+    p
+}
+
+/// Return x^n modulo the crc polynomial, bit-reversed.
+#[cfg(target_arch = "x86_64")]
+const fn x_pow_mod_p(mut n: u64) -> u32 {
+    let mut result = 1u32 << 31; // x^0
+    let mut square = 1u32 << 30; // x^1
+    while n != 0 {
+        if n & 1 != 0 {
+            result = mul_mod_p(result, square);
+        }
+        square = mul_mod_p(square, square);
+        n >>= 1;
+    }
+    result
+}
+
+/// Return the crc32c of concat(A, data[0,n-1]) where init_crc is the
+/// crc32c of some string A.  extend() is often used to maintain the
+/// crc32c of a stream of data.
+#[inline]
+pub fn extend(init_crc: u32, data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            if data.len() >= 3 * STREAM_LEN && is_x86_feature_detected!("pclmulqdq") {
+                return unsafe { extend_sse42_pclmul(init_crc, data) };
+            }
+            return unsafe { extend_sse42(init_crc, data) };
+        }
+    }
+    extend_portable(init_crc, data)
+}
+
 /// Return the crc32c of data[0,n-1]
 #[inline]
 pub fn value(data: &[u8]) -> u32 {
-    crc::crc32::checksum_castagnoli(data)
+    extend(0, data)
+}
+
+/// Portable slicing-by-8 implementation of `extend`.
+fn extend_portable(init_crc: u32, data: &[u8]) -> u32 {
+    let mut l = init_crc ^ 0xffffffff;
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let word = decode_fixed_64(chunk) ^ l as u64;
+        l = TABLES[7][(word & 0xff) as usize]
+            ^ TABLES[6][((word >> 8) & 0xff) as usize]
+            ^ TABLES[5][((word >> 16) & 0xff) as usize]
+            ^ TABLES[4][((word >> 24) & 0xff) as usize]
+            ^ TABLES[3][((word >> 32) & 0xff) as usize]
+            ^ TABLES[2][((word >> 40) & 0xff) as usize]
+            ^ TABLES[1][((word >> 48) & 0xff) as usize]
+            ^ TABLES[0][(word >> 56) as usize];
+    }
+    for &b in chunks.remainder() {
+        l = TABLES[0][((l ^ b as u32) & 0xff) as usize] ^ (l >> 8);
+    }
+
+    l ^ 0xffffffff
+}
+
+/// Implementation of `extend` using the SSE4.2 crc32 instruction.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn extend_sse42(init_crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut l = (init_crc ^ 0xffffffff) as u64;
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        l = _mm_crc32_u64(l, decode_fixed_64(chunk));
+    }
+    let mut l = l as u32;
+    for &b in chunks.remainder() {
+        l = _mm_crc32_u8(l, b);
+    }
+
+    l ^ 0xffffffff
+}
+
+/// Implementation of `extend` for long inputs using the SSE4.2 crc32 and
+/// the PCLMULQDQ carry-less multiply instructions.
+///
+/// A single crc32 chain is bound by the instruction's latency. Instead,
+/// every round crcs three adjacent streams independently (the second and
+/// third starting from zero), so the CPU can overlap them. The streams are
+/// then joined by linearity: crc(A||B||C) = crc(A)*x^(2L) ^ crc(B)*x^L ^
+/// crc(C) mod P, for streams of L bits. The clmul of two bit-reversed
+/// 32-bit values is their product times x, and crc32 of a 64-bit value
+/// from zero multiplies by x^32 and reduces mod P, so multiplying by
+/// x^(L-33) with clmul followed by crc32 shifts a crc by L bits.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2,pclmulqdq")]
+unsafe fn extend_sse42_pclmul(init_crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{
+        _mm_clmulepi64_si128, _mm_crc32_u64, _mm_cvtsi128_si64, _mm_cvtsi64_si128,
+    };
+
+    let clmul = |a: u64, b: u64| -> u64 {
+        let product = _mm_clmulepi64_si128(_mm_cvtsi64_si128(a as i64), _mm_cvtsi64_si128(b as i64), 0x00);
+        _mm_cvtsi128_si64(product) as u64
+    };
+
+    let mut l = (init_crc ^ 0xffffffff) as u64;
+
+    let mut rounds = data.chunks_exact(3 * STREAM_LEN);
+    for round in &mut rounds {
+        let (a, rest) = round.split_at(STREAM_LEN);
+        let (b, c) = rest.split_at(STREAM_LEN);
+        let (mut crc1, mut crc2) = (0u64, 0u64);
+        for i in (0..STREAM_LEN).step_by(8) {
+            l = _mm_crc32_u64(l, decode_fixed_64(&a[i..]));
+            crc1 = _mm_crc32_u64(crc1, decode_fixed_64(&b[i..]));
+            crc2 = _mm_crc32_u64(crc2, decode_fixed_64(&c[i..]));
+        }
+        let shifted = clmul(l, SHIFT_TWO_STREAMS) ^ clmul(crc1, SHIFT_ONE_STREAM);
+        l = _mm_crc32_u64(0, shifted) ^ crc2;
+    }
+
+    extend_sse42((l as u32) ^ 0xffffffff, rounds.remainder())
 }
 
 /// Return a masked representation of crc.
@@ -20,21 +199,24 @@ pub fn value(data: &[u8]) -> u32 {
 #[inline]
 pub fn mask(crc: u32) -> u32 {
     // Rotate right by 15 bits and add a constant.
-    ((crc >> 15) | (crc << 17)) + MASK_DELTA
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
 }
 
 /// Return the crc whose masked representation is masked_crc.
 #[inline]
 pub fn unmask(masked_crc: u32) -> u32 {
-    let rot = masked_crc - MASK_DELTA;
-    (rot >> 17) | (rot << 15)
+    let rot = masked_crc.wrapping_sub(MASK_DELTA);
+    rot.rotate_left(15)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::extend;
+    use super::extend_portable;
     use super::value;
     use super::mask;
     use super::unmask;
+    use crate::util::random::Random;
 
     #[test]
     pub fn standard_results() {
@@ -62,6 +244,7 @@ mod tests {
             0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         ];
         assert_eq!(value(&data), 0xd9963a56);
+        assert_eq!(extend_portable(0, &data), 0xd9963a56);
     }
 
     #[test]
@@ -69,6 +252,48 @@ mod tests {
         assert_ne!(value("a".as_bytes()), value("foo".as_bytes()));
     }
 
+    #[test]
+    pub fn extends() {
+        assert_eq!(value("hello world".as_bytes()), extend(value("hello ".as_bytes()), "world".as_bytes()));
+        assert_eq!(value(&[]), 0);
+        assert_eq!(extend(0x12345678, &[]), 0x12345678);
+    }
+
+    #[test]
+    pub fn portable_matches_accelerated() {
+        let rnd = Random::new(301);
+        let data: Vec<u8> = (0..1024).map(|_| rnd.uniform(256) as u8).collect();
+        for start in 0..16 {
+            for len in [0, 1, 7, 8, 9, 63, 64, 65, 1000].iter() {
+                let chunk = &data[start..start + len];
+                assert_eq!(extend(0, chunk), extend_portable(0, chunk));
+                assert_eq!(extend(0xdeadbeef, chunk), extend_portable(0xdeadbeef, chunk));
+            }
+        }
+    }
+
+    #[test]
+    pub fn pclmul_matches_portable() {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use super::{extend_sse42_pclmul, STREAM_LEN};
+
+            if !is_x86_feature_detected!("sse4.2") || !is_x86_feature_detected!("pclmulqdq") {
+                return;
+            }
+            let rnd = Random::new(301);
+            let data: Vec<u8> = (0..8 * STREAM_LEN).map(|_| rnd.uniform(256) as u8).collect();
+            for start in 0..8 {
+                for len in [0, 1, 3 * STREAM_LEN - 1, 3 * STREAM_LEN, 3 * STREAM_LEN + 5, 7 * STREAM_LEN].iter() {
+                    let chunk = &data[start..start + len];
+                    let expected = extend_portable(0xdeadbeef, chunk);
+                    assert_eq!(unsafe { extend_sse42_pclmul(0xdeadbeef, chunk) }, expected);
+                    assert_eq!(extend(0xdeadbeef, chunk), expected);
+                }
+            }
+        }
+    }
+
     #[test]
     pub fn mask_and_umask() {
         let crc = value("foo".as_bytes());
@@ -77,4 +302,4 @@ mod tests {
         assert_eq!(crc, unmask(mask(crc)));
         assert_eq!(crc, unmask(unmask(mask(mask(crc)))));
     }
-}
\ No newline at end of file
+}
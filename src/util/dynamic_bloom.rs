@@ -0,0 +1,184 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::util::hash::hash;
+
+// Same seed as the bloom filter policy in leveldb C++.
+const BLOOM_SEED: u32 = 0xbc9f1d34;
+
+// Largest size whose bit count, rounded up to a whole word, fits in a u32.
+const MAX_TOTAL_BITS: u32 = u32::MAX - 63;
+
+/// Return `total_bits` rounded up to a whole number of 64-bit words.
+///
+/// REQUIRES: total_bits <= 2^32 - 64
+fn round_up_to_word(total_bits: u32) -> u32 {
+    total_bits.div_ceil(64) * 64
+}
+
+/// An in-memory bloom filter that keys can be added to at any time, meant to
+/// sit in front of a memtable so point lookups for absent keys can skip the
+/// skiplist search. Similar to "DynamicBloom" in rocksdb.
+///
+/// `add` and `may_contain` both take a shared reference and may run
+/// concurrently from several threads.
+pub struct DynamicBloom {
+    bits: Box<[AtomicU64]>,
+    total_bits: u32,
+    num_probes: u32,
+}
+
+impl DynamicBloom {
+    /// Return a filter with room for `total_bits` bits (rounded up to a whole
+    /// word) that sets `num_probes` bits per key.
+    ///
+    /// REQUIRES: total_bits > 0, total_bits <= 2^32 - 64 and num_probes > 0
+    pub fn new(total_bits: u32, num_probes: u32) -> Self {
+        assert!(total_bits > 0 && total_bits <= MAX_TOTAL_BITS);
+        assert!(num_probes > 0);
+        let total_bits = round_up_to_word(total_bits);
+        Self {
+            bits: (0..total_bits / 64).map(|_| AtomicU64::new(0)).collect(),
+            total_bits,
+            num_probes,
+        }
+    }
+
+    /// Return a filter for a memtable whose write buffer is
+    /// `write_buffer_size` bytes, spending `size_ratio` of that budget on
+    /// the filter (e.g. 0.02 for 2%). With 6 probes the false positive rate
+    /// stays around 1% while there are at least 10 bits per entry, i.e. while
+    /// entries average at least `10 / (8 * size_ratio)` bytes (63 at 2%).
+    ///
+    /// REQUIRES: size_ratio > 0
+    pub fn for_write_buffer(write_buffer_size: usize, size_ratio: f64) -> Self {
+        assert!(size_ratio > 0.0);
+        let bits = (write_buffer_size as f64 * size_ratio * 8.0).clamp(64.0, MAX_TOTAL_BITS as f64);
+        Self::new(bits as u32, 6)
+    }
+
+    /// Add `key` to the filter.
+    pub fn add(&self, key: &[u8]) {
+        self.for_each_probe(key, |word, mask| {
+            // Skip the atomic read-modify-write if the bit is already set.
+            if word.load(Ordering::Relaxed) & mask == 0 {
+                word.fetch_or(mask, Ordering::Relaxed);
+            }
+            true
+        });
+    }
+
+    /// Return false if `key` was definitely never added to the filter, and
+    /// true if it may have been.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.for_each_probe(key, |word, mask| word.load(Ordering::Relaxed) & mask != 0)
+    }
+
+    /// Return the size of the filter in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<AtomicU64>()
+    }
+
+    /// Call `f` with the word and bit mask of every probe for `key`, stopping
+    /// early and returning false as soon as `f` does.
+    #[inline]
+    fn for_each_probe<F: FnMut(&AtomicU64, u64) -> bool>(&self, key: &[u8], mut f: F) -> bool {
+        // Use double-hashing to generate a sequence of hash values, as the
+        // bloom filter policy in leveldb does.
+        let mut h = hash(key, BLOOM_SEED);
+        let delta = h.rotate_right(17);
+        for _ in 0..self.num_probes {
+            let bitpos = h % self.total_bits;
+            if !f(&self.bits[(bitpos / 64) as usize], 1u64 << (bitpos % 64)) {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{round_up_to_word, DynamicBloom, MAX_TOTAL_BITS};
+    use crate::util::coding::encode_fixed_32;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn key(i: u32) -> [u8; 4] {
+        let mut buf = [0u8; 4];
+        encode_fixed_32(&mut buf, i);
+        buf
+    }
+
+    #[test]
+    fn empty() {
+        let bloom = DynamicBloom::new(100, 2);
+        assert_eq!(bloom.memory_usage(), 16);
+        assert!(!bloom.may_contain(b"hello"));
+        assert!(!bloom.may_contain(b"world"));
+    }
+
+    #[test]
+    fn small() {
+        let bloom = DynamicBloom::new(100, 2);
+        bloom.add(b"hello");
+        bloom.add(b"world");
+        assert!(bloom.may_contain(b"hello"));
+        assert!(bloom.may_contain(b"world"));
+        assert!(!bloom.may_contain(b"x"));
+        assert!(!bloom.may_contain(b"foo"));
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        const N: u32 = 10000;
+        // 10 bits per key
+        let bloom = DynamicBloom::new(N * 10, 6);
+        for i in 0..N {
+            bloom.add(&key(i));
+        }
+        for i in 0..N {
+            assert!(bloom.may_contain(&key(i)));
+        }
+        let false_positives = (N..N + 10000).filter(|i| bloom.may_contain(&key(*i))).count();
+        assert!(false_positives < 200, "{}", false_positives);
+    }
+
+    #[test]
+    fn write_buffer() {
+        let bloom = DynamicBloom::for_write_buffer(4 << 20, 0.02);
+        let budget = ((4 << 20) as f64 * 0.02) as usize;
+        assert!(bloom.memory_usage() >= budget && bloom.memory_usage() < budget + 8);
+        assert_eq!(DynamicBloom::for_write_buffer(0, 0.02).memory_usage(), 8);
+        assert_eq!(DynamicBloom::new(65, 1).total_bits, 128);
+        assert_eq!(round_up_to_word(1), 64);
+        assert_eq!(round_up_to_word(64), 64);
+        assert_eq!(round_up_to_word(MAX_TOTAL_BITS), MAX_TOTAL_BITS);
+    }
+
+    #[test]
+    fn concurrent() {
+        const THREADS: u32 = 4;
+        const N: u32 = 5000;
+        let bloom = Arc::new(DynamicBloom::new(THREADS * N * 10, 6));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let bloom = bloom.clone();
+                thread::spawn(move || {
+                    for i in t * N..(t + 1) * N {
+                        bloom.add(&key(i));
+                        assert!(bloom.may_contain(&key(i)));
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!((0..THREADS * N).all(|i| bloom.may_contain(&key(i))));
+    }
+}
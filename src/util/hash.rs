@@ -3,7 +3,18 @@
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
 use super::coding::decode_fixed_32;
+use super::xxhash::xxh3_64_with_seed;
 
+// Hashing contract
+//
+// Every function in this module is a pure function of its input bytes and
+// seed. The results are identical on every platform, endianness, pointer
+// width and build profile, and never change between releases, so they may
+// be persisted (e.g. inside filter blocks) and compared across processes.
+// A change to any output requires a new function, not a new implementation.
+
+/// Return the 32-bit hash of `data`, the same function as "Hash" in leveldb
+/// C++. Used by the bloom filter and the block cache.
 pub fn hash(data: &[u8], seed: u32) -> u32 {
     // Similar to murmur hash
     const M: u32 = 0xc6a4a793;
@@ -16,7 +27,7 @@ pub fn hash(data: &[u8], seed: u32) -> u32 {
     while i + 4 <= n {
         let w = decode_fixed_32(&data[i..]);
         i += 4;
-        h += w;
+        h = h.wrapping_add(w);
         h = h.wrapping_mul(M);
         h ^= h >> 16;
     }
@@ -24,13 +35,13 @@ pub fn hash(data: &[u8], seed: u32) -> u32 {
     // Pick up remaining bytes
     let remainder = n - i;
     if remainder > 2 {
-        h += (data[i + 2] as u32) << 16;
+        h = h.wrapping_add((data[i + 2] as u32) << 16);
     }
     if remainder > 1 {
-        h += (data[i + 1] as u32) << 8;
+        h = h.wrapping_add((data[i + 1] as u32) << 8);
     }
     if remainder > 0 {
-        h += data[i] as u32;
+        h = h.wrapping_add(data[i] as u32);
         h = h.wrapping_mul(M);
         h ^= h >> R;
     }
@@ -38,9 +49,35 @@ pub fn hash(data: &[u8], seed: u32) -> u32 {
     h
 }
 
+/// Return the 64-bit hash of `data`. This is XXH3-64 with the given seed,
+/// so values can be checked against any other XXH3 implementation.
+///
+/// Prefer this over `hash` wherever the number of hashed keys is large enough
+/// for 32-bit collisions to matter, such as large bloom filters, cache
+/// sharding and hash indexes.
+#[inline]
+pub fn hash64(data: &[u8], seed: u64) -> u64 {
+    xxh3_64_with_seed(data, seed)
+}
+
+/// Map `hash` uniformly onto the range [0, n) with a multiply and a shift
+/// instead of a division. Uses the upper bits of `hash`, which are the best
+/// mixed bits of `hash64`.
+#[inline]
+pub fn fast_range_64(hash: u64, n: usize) -> usize {
+    (((hash as u128) * (n as u128)) >> 64) as usize
+}
+
+/// Map `hash` uniformly onto the range [0, n) with a multiply and a shift
+/// instead of a division.
+#[inline]
+pub fn fast_range_32(hash: u32, n: u32) -> u32 {
+    (((hash as u64) * (n as u64)) >> 32) as u32
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::hash::hash;
+    use crate::util::hash::{fast_range_32, fast_range_64, hash, hash64};
 
     #[test]
     fn test() {
@@ -67,5 +104,26 @@ mod tests {
         assert_eq!(hash(&data4, 0xbc9f1d34), 0xed21633a);
         // 0xbc9f1d34 = 3164544308, 0xf333dabb = 4080261819
         assert_eq!(hash(&data5, 0x12345678), 0xf333dabb);
+
+        // Additions used to overflow (and panic in debug builds) here.
+        assert_eq!(hash(&[0xff; 11], 0xffffffff), 0x6e349478);
+    }
+
+    #[test]
+    fn test_hash64() {
+        assert_eq!(hash64(&[], 0), 0x2d06800538d394c2);
+        assert_eq!(hash64(b"hello world", 0), 0xd447b1ea40e6988b);
+        assert_ne!(hash64(b"hello world", 1), hash64(b"hello world", 0));
+    }
+
+    #[test]
+    fn fast_range() {
+        assert_eq!(fast_range_64(0, 10), 0);
+        assert_eq!(fast_range_64(u64::MAX, 10), 9);
+        assert_eq!(fast_range_64(1 << 63, 10), 5);
+        assert_eq!(fast_range_64(12345, 0), 0);
+        assert_eq!(fast_range_32(0, 7), 0);
+        assert_eq!(fast_range_32(u32::MAX, 7), 6);
+        assert_eq!(fast_range_32(1 << 31, 7), 3);
     }
 }
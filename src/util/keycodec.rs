@@ -0,0 +1,339 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use crate::util::slice::Slice;
+
+// Each field starts with a tag byte identifying its type. Tags are ordered
+// so that values of different types sort by type first. A descending field
+// is encoded like an ascending one and then has every byte inverted, so its
+// tag is always >= 0x80 and decoding can tell the two orders apart.
+const TAG_NULL: u8 = 0x05;
+const TAG_FALSE: u8 = 0x06;
+const TAG_TRUE: u8 = 0x07;
+const TAG_I64: u8 = 0x10;
+const TAG_U64: u8 = 0x11;
+const TAG_F64: u8 = 0x20;
+const TAG_BYTES: u8 = 0x30;
+const TAG_STR: u8 = 0x31;
+
+// Byte strings escape 0x00 as 0x00 0xff and end with 0x00 0x01, which keeps
+// them prefix-free and sorts a string before any of its extensions.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_00: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+/// A single field of a composite key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyValue {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    /// Ordered like `f64::total_cmp`: -NaN < -inf < ... < -0.0 < 0.0 < ... < inf < NaN.
+    F64(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// The direction in which a field sorts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// Builds a key out of typed fields so that comparing encoded keys with
+/// `Slice::compare` gives the same order as comparing the fields one by one.
+///
+/// All integers are encoded big-endian, unlike the little-endian
+/// `encode_fixed_32/64` helpers used for on-disk formats, since only
+/// big-endian bytes compare in numeric order.
+#[derive(Clone, Debug, Default)]
+pub struct KeyEncoder {
+    buf: Vec<u8>,
+}
+
+impl KeyEncoder {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Append `value` to the key, sorting in the direction given by `order`.
+    pub fn put(&mut self, value: &KeyValue, order: SortOrder) -> &mut Self {
+        let start = self.buf.len();
+        match value {
+            KeyValue::Null => self.buf.push(TAG_NULL),
+            KeyValue::Bool(false) => self.buf.push(TAG_FALSE),
+            KeyValue::Bool(true) => self.buf.push(TAG_TRUE),
+            KeyValue::U64(v) => {
+                self.buf.push(TAG_U64);
+                self.buf.extend_from_slice(&v.to_be_bytes());
+            }
+            KeyValue::I64(v) => {
+                self.buf.push(TAG_I64);
+                self.buf.extend_from_slice(&((*v as u64) ^ (1 << 63)).to_be_bytes());
+            }
+            KeyValue::F64(v) => {
+                self.buf.push(TAG_F64);
+                self.buf.extend_from_slice(&encode_f64(*v).to_be_bytes());
+            }
+            KeyValue::Str(s) => {
+                self.buf.push(TAG_STR);
+                self.put_escaped(s.as_bytes());
+            }
+            KeyValue::Bytes(b) => {
+                self.buf.push(TAG_BYTES);
+                self.put_escaped(b);
+            }
+        }
+        if order == SortOrder::Descending {
+            for b in &mut self.buf[start..] {
+                *b = !*b;
+            }
+        }
+        self
+    }
+
+    /// Return the key encoded so far.
+    pub fn as_slice(&self) -> Slice<'_> {
+        Slice::from(&self.buf)
+    }
+
+    /// Consume the encoder and return the encoded key.
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn put_escaped(&mut self, data: &[u8]) {
+        for &b in data {
+            self.buf.push(b);
+            if b == ESCAPE {
+                self.buf.push(ESCAPED_00);
+            }
+        }
+        self.buf.push(ESCAPE);
+        self.buf.push(TERMINATOR);
+    }
+}
+
+/// Encode `fields` into a single order-preserving key.
+pub fn encode_key(fields: &[(KeyValue, SortOrder)]) -> Vec<u8> {
+    let mut encoder = KeyEncoder::new();
+    for (value, order) in fields {
+        encoder.put(value, *order);
+    }
+    encoder.finish()
+}
+
+/// Decode a key produced by `KeyEncoder` back into its fields.
+///
+/// Return `None` if `key` is not a valid encoding.
+pub fn decode_key(key: &[u8]) -> Option<Vec<(KeyValue, SortOrder)>> {
+    let mut fields = Vec::new();
+    let mut input = key;
+    while !input.is_empty() {
+        let (field, rest) = decode_field(input)?;
+        fields.push(field);
+        input = rest;
+    }
+    Some(fields)
+}
+
+/// Decode the first field of `input`, and return it along with the rest of
+/// the input.
+fn decode_field(input: &[u8]) -> Option<((KeyValue, SortOrder), &[u8])> {
+    let (order, mask) = if input[0] & 0x80 == 0 {
+        (SortOrder::Ascending, 0x00)
+    } else {
+        (SortOrder::Descending, 0xff)
+    };
+    let tag = input[0] ^ mask;
+    let input = &input[1..];
+
+    let fixed_64 = |input: &[u8]| -> Option<u64> {
+        let bytes = input.get(..8)?;
+        let mut buf = [0u8; 8];
+        for (dst, src) in buf.iter_mut().zip(bytes) {
+            *dst = src ^ mask;
+        }
+        Some(u64::from_be_bytes(buf))
+    };
+
+    let (value, rest) = match tag {
+        TAG_NULL => (KeyValue::Null, input),
+        TAG_FALSE => (KeyValue::Bool(false), input),
+        TAG_TRUE => (KeyValue::Bool(true), input),
+        TAG_U64 => (KeyValue::U64(fixed_64(input)?), &input[8..]),
+        TAG_I64 => (KeyValue::I64((fixed_64(input)? ^ (1 << 63)) as i64), &input[8..]),
+        TAG_F64 => (KeyValue::F64(decode_f64(fixed_64(input)?)), &input[8..]),
+        TAG_STR => {
+            let (data, rest) = decode_escaped(input, mask)?;
+            (KeyValue::Str(String::from_utf8(data).ok()?), rest)
+        }
+        TAG_BYTES => {
+            let (data, rest) = decode_escaped(input, mask)?;
+            (KeyValue::Bytes(data), rest)
+        }
+        _ => return None,
+    };
+    Some(((value, order), rest))
+}
+
+fn decode_escaped(input: &[u8], mask: u8) -> Option<(Vec<u8>, &[u8])> {
+    let mut data = Vec::new();
+    let mut i = 0;
+    loop {
+        let b = *input.get(i)? ^ mask;
+        i += 1;
+        if b != ESCAPE {
+            data.push(b);
+            continue;
+        }
+        let next = *input.get(i)? ^ mask;
+        i += 1;
+        match next {
+            ESCAPED_00 => data.push(ESCAPE),
+            TERMINATOR => return Some((data, &input[i..])),
+            _ => return None,
+        }
+    }
+}
+
+/// Map `v` to an integer whose unsigned order is `f64::total_cmp` order.
+fn encode_f64(v: f64) -> u64 {
+    let bits = v.to_bits();
+    if bits & (1 << 63) != 0 {
+        !bits
+    } else {
+        bits ^ (1 << 63)
+    }
+}
+
+fn decode_f64(bits: u64) -> f64 {
+    if bits & (1 << 63) != 0 {
+        f64::from_bits(bits ^ (1 << 63))
+    } else {
+        f64::from_bits(!bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::random::{Random, RandomGenerator};
+    use std::cmp::Ordering;
+
+    fn type_rank(v: &KeyValue) -> u8 {
+        match v {
+            KeyValue::Null => 0,
+            KeyValue::Bool(_) => 1,
+            KeyValue::I64(_) => 2,
+            KeyValue::U64(_) => 3,
+            KeyValue::F64(_) => 4,
+            KeyValue::Bytes(_) => 5,
+            KeyValue::Str(_) => 6,
+        }
+    }
+
+    fn logical_cmp(a: &[(KeyValue, SortOrder)], b: &[(KeyValue, SortOrder)]) -> Ordering {
+        for ((x, order), (y, _)) in a.iter().zip(b) {
+            let ord = match (x, y) {
+                (KeyValue::Bool(x), KeyValue::Bool(y)) => x.cmp(y),
+                (KeyValue::U64(x), KeyValue::U64(y)) => x.cmp(y),
+                (KeyValue::I64(x), KeyValue::I64(y)) => x.cmp(y),
+                (KeyValue::F64(x), KeyValue::F64(y)) => x.total_cmp(y),
+                (KeyValue::Str(x), KeyValue::Str(y)) => x.cmp(y),
+                (KeyValue::Bytes(x), KeyValue::Bytes(y)) => x.cmp(y),
+                _ => type_rank(x).cmp(&type_rank(y)),
+            };
+            let ord = if *order == SortOrder::Descending { ord.reverse() } else { ord };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        a.len().cmp(&b.len())
+    }
+
+    fn random_value(rnd: &Random) -> KeyValue {
+        match rnd.uniform(7) {
+            0 => KeyValue::Null,
+            1 => KeyValue::Bool(rnd.one_in(2)),
+            2 => KeyValue::U64(if rnd.one_in(2) { rnd.uniform_u64(4) } else { rnd.next_u64() }),
+            3 => KeyValue::I64(if rnd.one_in(2) { rnd.uniform_u64(5) as i64 - 2 } else { rnd.next_u64() as i64 }),
+            4 => KeyValue::F64(match rnd.uniform(6) {
+                0 => 0.0,
+                1 => -0.0,
+                2 => f64::INFINITY,
+                3 => f64::NEG_INFINITY,
+                _ => (rnd.next_f64() - 0.5) * 1e6,
+            }),
+            5 => KeyValue::Str(String::from_utf8(rnd.random_bytes(rnd.uniform(4) as usize)).unwrap()),
+            _ => KeyValue::Bytes((0..rnd.uniform(4)).map(|_| [0x00, 0x01, 0xff][rnd.uniform(3) as usize]).collect()),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let fields = vec![
+            (KeyValue::Null, SortOrder::Ascending),
+            (KeyValue::Bool(true), SortOrder::Descending),
+            (KeyValue::U64(u64::MAX), SortOrder::Ascending),
+            (KeyValue::I64(-42), SortOrder::Descending),
+            (KeyValue::F64(-1.5), SortOrder::Ascending),
+            (KeyValue::Str("a\0b".to_string()), SortOrder::Descending),
+            (KeyValue::Bytes(vec![0, 0xff, 0, 1]), SortOrder::Ascending),
+            (KeyValue::Bytes(vec![]), SortOrder::Descending),
+        ];
+        let key = encode_key(&fields);
+        assert_eq!(decode_key(&key), Some(fields));
+        assert_eq!(decode_key(&[]), Some(vec![]));
+
+        let nan = encode_key(&[(KeyValue::F64(f64::NAN), SortOrder::Descending)]);
+        match &decode_key(&nan).unwrap()[0].0 {
+            KeyValue::F64(v) => assert_eq!(v.to_bits(), f64::NAN.to_bits()),
+            v => panic!("{:?}", v),
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(decode_key(&[0x42]), None);
+        assert_eq!(decode_key(&[TAG_U64, 1, 2, 3]), None);
+        assert_eq!(decode_key(&[TAG_STR, b'a']), None);
+        assert_eq!(decode_key(&[TAG_STR, b'a', 0x00, 0x02]), None);
+        assert_eq!(decode_key(&[TAG_STR, 0xc3, 0x28, 0x00, 0x01]), None);
+    }
+
+    #[test]
+    fn encoder() {
+        let mut encoder = KeyEncoder::new();
+        encoder
+            .put(&KeyValue::U64(1), SortOrder::Ascending)
+            .put(&KeyValue::Str("x".to_string()), SortOrder::Ascending);
+        assert_eq!(encoder.as_slice().slice_data(), &[TAG_U64, 0, 0, 0, 0, 0, 0, 0, 1, TAG_STR, b'x', 0, 1][..]);
+    }
+
+    #[test]
+    fn order() {
+        let rnd = Random::new(301);
+        for _ in 0..20000 {
+            let n = 1 + rnd.uniform(3) as usize;
+            let orders: Vec<SortOrder> = (0..n)
+                .map(|_| if rnd.one_in(2) { SortOrder::Ascending } else { SortOrder::Descending })
+                .collect();
+            let a: Vec<_> = orders.iter().map(|o| (random_value(&rnd), *o)).collect();
+            let b: Vec<_> = orders.iter().map(|o| (random_value(&rnd), *o)).collect();
+
+            let (ka, kb) = (encode_key(&a), encode_key(&b));
+            assert_eq!(
+                Slice::from(&ka).compare(&Slice::from(&kb)),
+                logical_cmp(&a, &b),
+                "{:?} {:?}",
+                a,
+                b
+            );
+            assert_eq!(decode_key(&ka).as_ref(), Some(&a));
+        }
+    }
+}
@@ -3,8 +3,14 @@
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
 pub mod arena;
+pub mod checksum;
+pub mod concurrent_arena;
 pub mod crc32c;
+pub mod dynamic_bloom;
 pub mod hash;
+pub mod keycodec;
 pub mod random;
+pub mod rate_limiter;
 pub mod slice;
+pub mod xxhash;
 mod coding;
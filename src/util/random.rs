@@ -3,66 +3,147 @@
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
 use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 /// A very simple random number generator.
 /// Not especially good at generating truly random bits,
 /// but good enough for our needs in this package.
+///
+/// `Random` is cheap but can only be used from one thread; use `SharedRandom`
+/// to share a generator between threads. Both produce the same sequence for
+/// the same seed. The distribution helpers live in `RandomGenerator`.
 pub struct Random {
     seed: Cell<u32>,
 }
 
+/// Like `Random`, but the seed is updated atomically so one generator can be
+/// shared between threads, e.g. by benchmark workers.
+pub struct SharedRandom {
+    seed: AtomicU32,
+}
+
+/// Return a seed that is safe to use with the generator.
+fn sanitize_seed(s: u32) -> u32 {
+    let seed = s & 0x7fffffffu32;
+    // Avoid bad seeds.
+    if seed == 0 || seed == 2147483647 {
+        1
+    } else {
+        seed
+    }
+}
+
+/// Return the seed that follows `seed`.
+fn next_seed(seed: u32) -> u32 {
+    // M = 2^31-1
+    const M: u32 = 2147483647;
+    // A = 0b0100_0001_1010_0111
+    const A: u64 = 16807;
+
+    // We are computing
+    //       seed = (seed * A) % M,    where M = 2^31-1
+    //
+    // seed must not be zero or M, or else all subsequent computed values
+    // will be zero or M respectively. For all other values, seed will end
+    // up cycling through every number in [1,M-1].
+    let product: u64 = (seed as u64) * A;
+
+    // To avoid the 64-bit division, compute (product % M) using the fact:
+    //       ((x << 31) % M) == x.
+    let mut seed = ((product >> 31) as u32) + ((product as u32) & M);
+
+    // The first reduction may overflow by 1 bit, so we may need to repeat.
+    // mod == M is not possible; using > allows the faster sign-bit-based test.
+    if seed > M {
+        seed -= M;
+    }
+
+    seed
+}
+
 impl Random {
     /// Return a random number generator.
     pub fn new(s: u32) -> Self {
-        let mut seed = s & 0x7fffffffu32;
-        // Avoid bad seeds.
-        if seed == 0 || seed == 2147483647 {
-            seed = 1;
-        }
         Self {
-            seed: Cell::new(seed),
+            seed: Cell::new(sanitize_seed(s)),
         }
     }
 
+    // The methods below forward to `RandomGenerator` so that callers that
+    // only need them don't have to import the trait.
+
     /// Return the next random number in this generator.
+    #[inline]
     pub fn next(&self) -> u32 {
-        // M = 2^31-1
-        const M: u32 = 2147483647;
-        // A = 0b0100_0001_1010_0111
-        const A: u64 = 16807;
-
-        // We are computing
-        //       seed = (seed * A) % M,    where M = 2^31-1
-        //
-        // seed must not be zero or M, or else all subsequent computed values
-        // will be zero or M respectively. For all other values, seed will end
-        // up cycling through every number in [1,M-1].
-        let product: u64 = (self.seed.get() as u64) * A;
-
-        // To avoid the 64-bit division, compute (product % M) using the fact:
-        //       ((x << 31) % M) == x.
-        self.seed.set(((product >> 31) as u32) + ((product as u32) & M));
-
-        // The first reduction may overflow by 1 bit, so we may need to repeat.
-        // mod == M is not possible; using > allows the faster sign-bit-based test.
-        if self.seed.get() > M {
-            self.seed.set(self.seed.get() - M);
+        RandomGenerator::next(self)
+    }
+
+    /// Returns a uniformly distributed value in the range [0..n-1]
+    /// REQUIRES: n > 0
+    #[inline]
+    pub fn uniform(&self, n: u32) -> u32 {
+        RandomGenerator::uniform(self, n)
+    }
+
+    /// Randomly returns true ~"1/n" of the time, and false otherwise.
+    /// REQUIRES: n > 0
+    #[inline]
+    pub fn one_in(&self, n: u32) -> bool {
+        RandomGenerator::one_in(self, n)
+    }
+
+    /// Skewed: pick "base" uniformly from range [0,max_log] and then
+    /// return "base" random bits. The effect is to pick a number in the
+    /// range [0,2^max_log-1] with exponential bias towards smaller numbers.
+    #[inline]
+    pub fn skewed(&self, max_log: u32) -> u32 {
+        RandomGenerator::skewed(self, max_log)
+    }
+}
+
+impl SharedRandom {
+    /// Return a random number generator that can be shared between threads.
+    pub fn new(s: u32) -> Self {
+        Self {
+            seed: AtomicU32::new(sanitize_seed(s)),
         }
+    }
+}
 
+impl RandomGenerator for Random {
+    fn next(&self) -> u32 {
+        self.seed.set(next_seed(self.seed.get()));
         self.seed.get()
     }
+}
+
+impl RandomGenerator for SharedRandom {
+    fn next(&self) -> u32 {
+        let prev = self
+            .seed
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |seed| Some(next_seed(seed)))
+            .unwrap();
+        next_seed(prev)
+    }
+}
+
+/// Random values and distributions derived from a generator's sequence of
+/// 31-bit numbers.
+pub trait RandomGenerator {
+    /// Return the next random number in this generator, in the range [1, 2^31-2].
+    fn next(&self) -> u32;
 
     /// Returns a uniformly distributed value in the range [0..n-1]
     /// REQUIRES: n > 0
     #[inline]
-    pub fn uniform(&self, n: u32) -> u32 {
+    fn uniform(&self, n: u32) -> u32 {
         self.next() % n
     }
 
     /// Randomly returns true ~"1/n" of the time, and false otherwise.
     /// REQUIRES: n > 0
     #[inline]
-    pub fn one_in(&self, n: u32) -> bool {
+    fn one_in(&self, n: u32) -> bool {
         self.next() % n == 0
     }
 
@@ -70,14 +151,114 @@ impl Random {
     /// return "base" random bits. The effect is to pick a number in the
     /// range [0,2^max_log-1] with exponential bias towards smaller numbers.
     #[inline]
-    pub fn skewed(&self, max_log: u32) -> u32 {
+    fn skewed(&self, max_log: u32) -> u32 {
         self.uniform(1 << self.uniform(max_log + 1))
     }
+
+    /// Return a random number covering all 64 bits, built from three
+    /// successive 31-bit numbers.
+    fn next_u64(&self) -> u64 {
+        let a = self.next() as u64;
+        let b = self.next() as u64;
+        let c = self.next() as u64;
+        (a << 33) ^ (b << 2) ^ c
+    }
+
+    /// Returns a uniformly distributed value in the range [0..n-1]
+    /// REQUIRES: n > 0
+    #[inline]
+    fn uniform_u64(&self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns a uniformly distributed value in the range [0, 1).
+    #[inline]
+    fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an exponentially distributed value with the given mean, e.g.
+    /// for the gaps between arrivals in a Poisson process.
+    /// REQUIRES: mean > 0
+    #[inline]
+    fn exponential(&self, mean: f64) -> f64 {
+        -mean * (1.0 - self.next_f64()).ln()
+    }
+
+    /// Return a string of `len` random printable bytes.
+    fn random_bytes(&self, len: usize) -> Vec<u8> {
+        // ' ' .. '~'
+        (0..len).map(|_| b' ' + self.uniform(95) as u8).collect()
+    }
+
+    /// Return a string of `len` bytes that compresses to roughly
+    /// `compressed_fraction` of its length, by repeating a random prefix.
+    fn compressible_bytes(&self, len: usize, compressed_fraction: f64) -> Vec<u8> {
+        let raw = ((len as f64 * compressed_fraction) as usize).max(1);
+        let raw_data = self.random_bytes(raw);
+
+        // Duplicate the random data until we have filled "len" bytes
+        let mut dst = Vec::with_capacity(len);
+        while dst.len() < len {
+            let n = raw_data.len().min(len - dst.len());
+            dst.extend_from_slice(&raw_data[..n]);
+        }
+        dst
+    }
+}
+
+/// Generates item indexes in [0, n) following a Zipfian distribution, where
+/// index 0 is the most popular. Uses the algorithm from "Quickly Generating
+/// Billion-Record Synthetic Databases" (Gray et al.), as YCSB does.
+pub struct Zipfian {
+    items: u64,
+    theta: f64,
+    zetan: f64,
+    alpha: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    /// Return a generator over `items` items with skew `theta`. A larger
+    /// `theta` is more skewed; YCSB uses 0.99.
+    ///
+    /// Takes O(items) time to precompute the normalization constant.
+    /// REQUIRES: items > 0 and 0 < theta < 1
+    pub fn new(items: u64, theta: f64) -> Self {
+        assert!(items > 0);
+        assert!(theta > 0.0 && theta < 1.0);
+        let zetan: f64 = (1..=items).map(|i| 1.0 / (i as f64).powf(theta)).sum();
+        let zeta2 = 1.0 + 0.5f64.powf(theta);
+        let eta = (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta2 / zetan);
+        Self {
+            items,
+            theta,
+            zetan,
+            alpha: 1.0 / (1.0 - theta),
+            eta,
+        }
+    }
+
+    /// Return the next item index using randomness from `rnd`.
+    pub fn next<R: RandomGenerator + ?Sized>(&self, rnd: &R) -> u64 {
+        let u = rnd.next_f64();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.items - 1);
+        }
+        let item = (self.items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        item.min(self.items - 1)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::util::random::Random;
+    use crate::util::random::{Random, RandomGenerator, SharedRandom, Zipfian};
+    use std::sync::Arc;
+    use std::thread;
 
     #[test]
     fn random() {
@@ -94,4 +275,87 @@ mod tests {
         assert_eq!(r.one_in(5), false);
         assert_eq!(r.skewed(3), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn shared() {
+        let r = Random::new(301);
+        let s = SharedRandom::new(301);
+        for _ in 0..1000 {
+            assert_eq!(r.next(), s.next());
+        }
+
+        const THREADS: usize = 4;
+        const N: usize = 10000;
+        let s = Arc::new(SharedRandom::new(7));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let s = s.clone();
+                thread::spawn(move || (0..N).map(|_| s.next()).collect::<Vec<u32>>())
+            })
+            .collect();
+        let mut values: Vec<u32> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+
+        // The threads consumed exactly the single-threaded sequence between them.
+        let r = Random::new(7);
+        let mut expected: Vec<u32> = (0..THREADS * N).map(|_| r.next()).collect();
+        values.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn wide() {
+        let r = Random::new(301);
+        let mut bits = 0u64;
+        for _ in 0..100 {
+            bits |= r.next_u64();
+            assert!(r.uniform_u64(1 << 40) < 1 << 40);
+            let f = r.next_f64();
+            assert!((0.0..1.0).contains(&f));
+        }
+        assert_eq!(bits, u64::MAX);
+    }
+
+    #[test]
+    fn exponential() {
+        const N: usize = 100000;
+        let r = Random::new(301);
+        let mean = (0..N).map(|_| r.exponential(10.0)).sum::<f64>() / N as f64;
+        assert!((mean - 10.0).abs() < 0.5, "{}", mean);
+    }
+
+    #[test]
+    fn strings() {
+        let r = Random::new(301);
+        let s = r.random_bytes(100);
+        assert_eq!(s.len(), 100);
+        assert!(s.iter().all(|b| (b' '..=b'~').contains(b)));
+
+        let s = r.compressible_bytes(1000, 0.25);
+        assert_eq!(s.len(), 1000);
+        for i in 250..1000 {
+            assert_eq!(s[i], s[i % 250]);
+        }
+        assert_eq!(r.compressible_bytes(10, 0.0).len(), 10);
+    }
+
+    #[test]
+    fn zipfian() {
+        const ITEMS: u64 = 1000;
+        const N: usize = 100000;
+        let r = Random::new(301);
+        let z = Zipfian::new(ITEMS, 0.99);
+        let mut counts = vec![0usize; ITEMS as usize];
+        for _ in 0..N {
+            counts[z.next(&r) as usize] += 1;
+        }
+        assert!(counts[0] > counts[1]);
+        assert!(counts[1] > counts[10]);
+        // The most popular tenth of the items gets the bulk of the accesses.
+        let head: usize = counts[..(ITEMS / 10) as usize].iter().sum();
+        assert!(head > N / 2, "{}", head);
+
+        let single = Zipfian::new(1, 0.5);
+        assert!((0..100).all(|_| single.next(&r) == 0));
+    }
+}
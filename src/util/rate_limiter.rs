@@ -0,0 +1,441 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::util::random::Random;
+
+// In auto-tuned mode the rate is re-evaluated once every this many refill
+// periods.
+const REFILLS_PER_TUNE: u32 = 100;
+
+// In auto-tuned mode the rate never drops below 1/20 of the configured rate.
+const ALLOWED_RANGE_FACTOR: u64 = 20;
+
+// In auto-tuned mode, if fewer than this percentage of refill periods ran out
+// of bytes the rate is lowered, and if more than HIGH_WATERMARK_PCT did it is
+// raised, by ADJUST_FACTOR_PCT percent each time.
+const LOW_WATERMARK_PCT: u64 = 50;
+const HIGH_WATERMARK_PCT: u64 = 90;
+const ADJUST_FACTOR_PCT: u64 = 5;
+
+/// Priority of an I/O request. Flushes should use `High` and compactions
+/// `Low`, so that flushes, which can stall foreground writes, go first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    Low = 0,
+    High = 1,
+}
+
+/// Options to control the behavior of a `RateLimiter`.
+#[derive(Clone, Debug)]
+pub struct RateLimiterOptions {
+    /// Total bytes per second that may be written. In auto-tuned mode this
+    /// is the upper bound of the rate instead.
+    ///
+    /// REQUIRES: rate_bytes_per_sec > 0
+    pub rate_bytes_per_sec: u64,
+
+    /// How often tokens are added to the bucket. A longer period lets larger
+    /// bursts through; a shorter one costs more wakeups.
+    ///
+    /// REQUIRES: refill_period > 0
+    ///
+    /// Default: 100ms
+    pub refill_period: Duration,
+
+    /// When both high and low priority requests are waiting, low priority
+    /// requests are served first with probability 1/fairness, so that they
+    /// are not starved.
+    ///
+    /// Default: 10
+    pub fairness: u32,
+
+    /// If true, the rate starts at half of rate_bytes_per_sec and is then
+    /// adjusted between rate_bytes_per_sec / 20 and rate_bytes_per_sec,
+    /// depending on how often requests have to wait.
+    ///
+    /// Default: false
+    pub auto_tuned: bool,
+}
+
+impl Default for RateLimiterOptions {
+    fn default() -> Self {
+        Self {
+            rate_bytes_per_sec: 64 << 20,
+            refill_period: Duration::from_millis(100),
+            fairness: 10,
+            auto_tuned: false,
+        }
+    }
+}
+
+/// A token bucket that limits the throughput of background writes, such as
+/// flushes and compactions, so that they don't starve foreground reads.
+/// Similar to "GenericRateLimiter" in rocksdb.
+///
+/// Every refill period the bucket is topped up with the bytes that the
+/// current rate allows per period. Requests that don't fit wait in a queue
+/// per priority and are granted, in order, as bytes become available. A
+/// request larger than one period's worth of bytes is granted in parts
+/// over several periods.
+pub struct RateLimiter {
+    options: RateLimiterOptions,
+    state: Mutex<State>,
+    cv: Condvar,
+}
+
+struct State {
+    rate_bytes_per_sec: u64,
+    refill_bytes_per_period: u64,
+    available_bytes: u64,
+    next_refill: Instant,
+    queues: [VecDeque<Request>; 2],
+    rnd: Random,
+
+    total_bytes_through: [u64; 2],
+    total_requests: [u64; 2],
+
+    // Auto-tuning bookkeeping
+    tuned_time: Instant,
+    num_drains: u64,
+}
+
+struct Request {
+    // Bytes that have yet to be granted
+    remaining: u64,
+    granted: Arc<AtomicBool>,
+}
+
+impl RateLimiter {
+    /// Return a limiter allowing `rate_bytes_per_sec` bytes per second with
+    /// the default options otherwise.
+    pub fn new(rate_bytes_per_sec: u64) -> Self {
+        Self::with_options(RateLimiterOptions {
+            rate_bytes_per_sec,
+            ..RateLimiterOptions::default()
+        })
+    }
+
+    /// Return a limiter configured by `options`.
+    pub fn with_options(options: RateLimiterOptions) -> Self {
+        assert!(options.rate_bytes_per_sec > 0);
+        assert!(options.refill_period > Duration::from_nanos(0));
+        let rate = if options.auto_tuned {
+            options.rate_bytes_per_sec / 2
+        } else {
+            options.rate_bytes_per_sec
+        };
+        let refill_bytes = Self::calculate_refill_bytes(&options, rate);
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(State {
+                rate_bytes_per_sec: rate,
+                refill_bytes_per_period: refill_bytes,
+                available_bytes: 0,
+                next_refill: now,
+                queues: [VecDeque::new(), VecDeque::new()],
+                rnd: Random::new(0xdeadbeef),
+                total_bytes_through: [0; 2],
+                total_requests: [0; 2],
+                tuned_time: now,
+                num_drains: 0,
+            }),
+            cv: Condvar::new(),
+            options,
+        }
+    }
+
+    /// Block until `bytes` bytes may be written at priority `pri`.
+    pub fn request(&self, bytes: usize, pri: IoPriority) {
+        let bytes = bytes as u64;
+        let mut state = self.state.lock().unwrap();
+        if self.options.auto_tuned {
+            self.tune(&mut state, Instant::now());
+        }
+        state.total_requests[pri as usize] += 1;
+        state.total_bytes_through[pri as usize] += bytes;
+
+        if state.queues.iter().all(|q| q.is_empty()) && state.available_bytes >= bytes {
+            state.available_bytes -= bytes;
+            return;
+        }
+
+        let granted = Arc::new(AtomicBool::new(false));
+        state.queues[pri as usize].push_back(Request {
+            remaining: bytes,
+            granted: granted.clone(),
+        });
+        loop {
+            // Whichever waiter wakes up first after the refill time refills
+            // the bucket on behalf of everyone else.
+            let now = Instant::now();
+            if now >= state.next_refill {
+                self.refill(&mut state, now);
+            }
+            if granted.load(Ordering::Relaxed) {
+                return;
+            }
+            let timeout = state.next_refill.saturating_duration_since(now);
+            state = self.cv.wait_timeout(state, timeout).unwrap().0;
+            if granted.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+    }
+
+    /// Return the number of bytes that may be requested in a single burst,
+    /// i.e. that are added to the bucket every refill period.
+    pub fn single_burst_bytes(&self) -> u64 {
+        self.state.lock().unwrap().refill_bytes_per_period
+    }
+
+    /// Return the current rate in bytes per second.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.state.lock().unwrap().rate_bytes_per_sec
+    }
+
+    /// Change the rate to `rate_bytes_per_sec`, starting from the next
+    /// refill period. In auto-tuned mode the rate keeps being tuned from the
+    /// new value.
+    ///
+    /// REQUIRES: rate_bytes_per_sec > 0
+    pub fn set_bytes_per_second(&self, rate_bytes_per_sec: u64) {
+        assert!(rate_bytes_per_sec > 0);
+        let mut state = self.state.lock().unwrap();
+        self.set_rate(&mut state, rate_bytes_per_sec);
+    }
+
+    /// Return the total number of bytes requested at priority `pri`.
+    pub fn total_bytes_through(&self, pri: IoPriority) -> u64 {
+        self.state.lock().unwrap().total_bytes_through[pri as usize]
+    }
+
+    /// Return the total number of requests made at priority `pri`.
+    pub fn total_requests(&self, pri: IoPriority) -> u64 {
+        self.state.lock().unwrap().total_requests[pri as usize]
+    }
+
+    fn calculate_refill_bytes(options: &RateLimiterOptions, rate_bytes_per_sec: u64) -> u64 {
+        let bytes = rate_bytes_per_sec as u128 * options.refill_period.as_nanos() / 1_000_000_000;
+        (bytes as u64).max(1)
+    }
+
+    fn set_rate(&self, state: &mut State, rate_bytes_per_sec: u64) {
+        state.rate_bytes_per_sec = rate_bytes_per_sec;
+        state.refill_bytes_per_period = Self::calculate_refill_bytes(&self.options, rate_bytes_per_sec);
+    }
+
+    /// Top up the bucket and grant as many queued requests as it allows.
+    fn refill(&self, state: &mut State, now: Instant) {
+        state.next_refill = now + self.options.refill_period;
+        if state.available_bytes < state.refill_bytes_per_period {
+            state.available_bytes += state.refill_bytes_per_period;
+        }
+
+        let low_first = state.rnd.one_in(self.options.fairness.max(1));
+        let order = if low_first {
+            [IoPriority::Low, IoPriority::High]
+        } else {
+            [IoPriority::High, IoPriority::Low]
+        };
+        let mut woke = false;
+        for pri in order.iter() {
+            let queue = &mut state.queues[*pri as usize];
+            while let Some(req) = queue.front_mut() {
+                if state.available_bytes < req.remaining {
+                    // Grant what we can now; the rest waits for the next
+                    // refill.
+                    req.remaining -= state.available_bytes;
+                    state.available_bytes = 0;
+                    break;
+                }
+                state.available_bytes -= req.remaining;
+                req.granted.store(true, Ordering::Relaxed);
+                queue.pop_front();
+                woke = true;
+            }
+        }
+        if state.available_bytes == 0 {
+            state.num_drains += 1;
+        }
+        if woke {
+            self.cv.notify_all();
+        }
+    }
+
+    /// Adjust the rate to how often the bucket ran dry over the last
+    /// REFILLS_PER_TUNE refill periods.
+    fn tune(&self, state: &mut State, now: Instant) {
+        let period = self.options.refill_period;
+        let elapsed = now.saturating_duration_since(state.tuned_time);
+        if elapsed < period * REFILLS_PER_TUNE {
+            return;
+        }
+        let intervals = ((elapsed.as_nanos() / period.as_nanos()) as u64).max(1);
+        let drained_pct = state.num_drains * 100 / intervals;
+
+        let max_rate = self.options.rate_bytes_per_sec;
+        let min_rate = (max_rate / ALLOWED_RANGE_FACTOR).max(1);
+        let prev_rate = state.rate_bytes_per_sec;
+        let new_rate = if drained_pct == 0 {
+            min_rate
+        } else if drained_pct < LOW_WATERMARK_PCT {
+            // Dividing by (100 + x) undoes a previous increase by x percent.
+            (prev_rate as u128 * 100 / (100 + ADJUST_FACTOR_PCT) as u128) as u64
+        } else if drained_pct > HIGH_WATERMARK_PCT {
+            (prev_rate as u128 * (100 + ADJUST_FACTOR_PCT) as u128 / 100) as u64
+        } else {
+            prev_rate
+        };
+        let new_rate = new_rate.clamp(min_rate, max_rate);
+        if new_rate != prev_rate {
+            self.set_rate(state, new_rate);
+        }
+
+        state.tuned_time = now;
+        state.num_drains = 0;
+    }
+
+    #[cfg(test)]
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IoPriority, RateLimiter, RateLimiterOptions};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn rate() {
+        // 10KB per 10ms period
+        let limiter = RateLimiter::with_options(RateLimiterOptions {
+            rate_bytes_per_sec: 1 << 20,
+            refill_period: Duration::from_millis(10),
+            ..RateLimiterOptions::default()
+        });
+        assert_eq!(limiter.single_burst_bytes(), 10485);
+
+        let start = Instant::now();
+        for _ in 0..20 {
+            limiter.request(10000, IoPriority::High);
+        }
+        // A request larger than a burst is granted over several periods.
+        limiter.request(50000, IoPriority::Low);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+        assert_eq!(limiter.total_requests(IoPriority::High), 20);
+        assert_eq!(limiter.total_bytes_through(IoPriority::High), 200000);
+        assert_eq!(limiter.total_requests(IoPriority::Low), 1);
+        assert_eq!(limiter.total_bytes_through(IoPriority::Low), 50000);
+    }
+
+    #[test]
+    fn set_bytes_per_second() {
+        let limiter = RateLimiter::new(1 << 20);
+        assert_eq!(limiter.bytes_per_second(), 1 << 20);
+        assert_eq!(limiter.single_burst_bytes(), 104857);
+        limiter.set_bytes_per_second(10 << 20);
+        assert_eq!(limiter.bytes_per_second(), 10 << 20);
+        assert_eq!(limiter.single_burst_bytes(), 1048576);
+    }
+
+    #[test]
+    fn priority() {
+        let limiter = Arc::new(RateLimiter::with_options(RateLimiterOptions {
+            rate_bytes_per_sec: 1 << 20,
+            refill_period: Duration::from_millis(10),
+            ..RateLimiterOptions::default()
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let handles: Vec<_> = [IoPriority::High, IoPriority::Low]
+            .iter()
+            .flat_map(|&pri| (0..2).map(move |_| pri))
+            .map(|pri| {
+                let limiter = limiter.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        limiter.request(4096, pri);
+                    }
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(300));
+        stop.store(true, Ordering::Relaxed);
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let high = limiter.total_bytes_through(IoPriority::High);
+        let low = limiter.total_bytes_through(IoPriority::Low);
+        assert!(low > 0);
+        assert!(high > 2 * low, "high {} low {}", high, low);
+    }
+
+    #[test]
+    fn auto_tune() {
+        let limiter = RateLimiter::with_options(RateLimiterOptions {
+            rate_bytes_per_sec: 100 << 20,
+            refill_period: Duration::from_millis(1),
+            auto_tuned: true,
+            ..RateLimiterOptions::default()
+        });
+        assert_eq!(limiter.bytes_per_second(), 50 << 20);
+
+        // Every period ran dry: the rate goes up.
+        let mut state = limiter.lock_state();
+        let now = state.tuned_time + Duration::from_millis(100);
+        state.num_drains = 100;
+        limiter.tune(&mut state, now);
+        assert_eq!(state.rate_bytes_per_sec, (50 << 20) * 105 / 100);
+
+        // Tuning waits for enough refill periods to pass.
+        state.num_drains = 100;
+        limiter.tune(&mut state, now + Duration::from_millis(50));
+        assert_eq!(state.rate_bytes_per_sec, (50 << 20) * 105 / 100);
+
+        // Some periods ran dry: the rate goes down.
+        state.num_drains = 20;
+        limiter.tune(&mut state, now + Duration::from_millis(100));
+        assert_eq!(state.rate_bytes_per_sec, 50 << 20);
+
+        // Nothing waited: the rate drops to the minimum.
+        state.num_drains = 0;
+        limiter.tune(&mut state, now + Duration::from_millis(200));
+        assert_eq!(state.rate_bytes_per_sec, (100 << 20) / 20);
+        assert_eq!(state.refill_bytes_per_period, (100 << 20) / 20 / 1000);
+
+        // The rate never exceeds the configured one.
+        state.rate_bytes_per_sec = 99 << 20;
+        state.num_drains = 100;
+        limiter.tune(&mut state, now + Duration::from_millis(300));
+        assert_eq!(state.rate_bytes_per_sec, 100 << 20);
+        drop(state);
+
+        // Periods shorter than a microsecond still work.
+        let limiter = RateLimiter::with_options(RateLimiterOptions {
+            rate_bytes_per_sec: 1 << 30,
+            refill_period: Duration::from_nanos(500),
+            auto_tuned: true,
+            ..RateLimiterOptions::default()
+        });
+        assert_eq!(limiter.single_burst_bytes(), 268);
+        let mut state = limiter.lock_state();
+        let now = state.tuned_time + Duration::from_micros(50);
+        state.num_drains = 100;
+        limiter.tune(&mut state, now);
+        assert_eq!(state.rate_bytes_per_sec, (512 << 20) * 105 / 100);
+    }
+}
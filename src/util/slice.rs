@@ -2,102 +2,84 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file. See the AUTHORS file for names of contributors.
 
-use std::ptr;
-use std::slice;
 use std::cmp::Ordering;
-use std::ops::Index;
-use std::hash::Hash;
-
-extern crate rlibc;
-
-/// Just like Rust's slice, except there's no borrowing.
-/// Instead, the user needs to guarantee that the instances of this struct
-/// should not live longer than the memory that `data` points to.
-#[derive(Clone, Debug, Eq, Hash)]
-pub struct Slice {
-    data: *const u8,
-    size: usize,
+use std::fmt;
+use std::ops::Deref;
+use std::slice;
+
+/// Similar to "Slice" in leveldb C++: a cheap, copyable view of a byte range.
+/// The lifetime `'a` ties the slice to the memory it refers to, so a `Slice`
+/// can never outlive the buffer it was created from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Slice<'a> {
+    data: &'a [u8],
 }
 
-impl Slice {
-    /// Create a slice that refers to data[0,n-1].
-    pub fn new(d: *const u8, n: usize) -> Self {
+impl<'a> Slice<'a> {
+    /// Create a slice that refers to d[0,n-1].
+    ///
+    /// # Safety
+    ///
+    /// `d` must be valid for reads of `n` bytes for the whole lifetime `'a`,
+    /// and the memory must not be mutated during that lifetime.
+    pub unsafe fn new(d: *const u8, n: usize) -> Self {
+        if n == 0 {
+            return Self::new_empty();
+        }
         Self {
-            data: d,
-            size: n,
+            data: slice::from_raw_parts(d, n),
         }
     }
 
     /// Create an empty slice.
     pub fn new_empty() -> Self {
-        Self {
-            data: ptr::null(),
-            size: 0,
-        }
+        Self { data: &[] }
     }
 
     /// Create a slice that refers to the contents of "s".
-    pub fn new_from_string(str: String) -> Self {
-        Self {
-            data: str.as_ptr(),
-            size: str.len(),
-        }
+    pub fn new_from_string(s: &'a str) -> Self {
+        Self { data: s.as_bytes() }
     }
 
     /// Return a pointer to the referenced data.
     #[inline]
     pub fn raw_ptr_data(&self) -> *const u8 {
-        self.data
+        self.data.as_ptr()
     }
 
     /// Return a slice to the referenced data.
     #[inline]
-    pub fn slice_data(&self) -> &[u8] {
-        unsafe {
-            slice::from_raw_parts(self.data, self.size)
-        }
+    pub fn slice_data(&self) -> &'a [u8] {
+        self.data
     }
 
     /// Return the length (in bytes) of the referenced data.
     #[inline]
     pub fn size(&self) -> usize {
-        self.size
+        self.data.len()
     }
 
     /// Return true iff the length of the referenced data is zero.
     #[inline]
     pub fn empty(&self) -> bool {
-        self.size == 0
+        self.data.is_empty()
     }
 
     /// Change this slice to refer to an empty array.
     #[inline]
     pub fn clear(&mut self) {
-        self.data = ptr::null();
-        self.size = 0;
+        self.data = &[];
     }
 
     /// Drop the first "n" bytes from this slice.
     pub fn remove_prefix(&mut self, n: usize) {
-        assert!(n <= self.size);
-        unsafe {
-            self.data = self.data.offset(n as isize);
-        }
-        self.size -= n;
-    }
-
-    /// Return a string that contains the copy of the referenced data.
-    pub fn to_string(&self) -> String {
-        unsafe {
-            ::std::str::from_utf8_unchecked(self.slice_data())
-        }.to_string()
+        assert!(n <= self.size());
+        self.data = &self.data[n..];
     }
 
     /// Return true iff "x" is a prefix of "self".
     pub fn starts_with(&self, x: &Slice) -> bool {
-        (self.size >= x.size) && unsafe {
-            rlibc::memcmp(self.data, x.data, x.size) == 0
-        }
+        self.data.starts_with(x.data)
     }
 
     /// Three-way comparison. Returns value:
@@ -105,66 +87,128 @@ impl Slice {
     ///   `Ordering::Equal`   iff `self` = `b`
     ///   `Ordering::Greater` iff `self` > `b`
     pub fn compare(&self, b: &Slice) -> Ordering {
-        let min_len = if self.size < b.size {
-            self.size
-        } else {
-            b.size
-        };
-
-        let r = unsafe { rlibc::memcmp(self.data, b.data, min_len) };
-        if r == 0 {
-            if self.size < b.size {
-                Ordering::Less
-            } else if self.size > b.size {
-                Ordering::Greater
-            } else {
-                Ordering::Equal
-            }
-        } else if r < 0 {
-            Ordering::Less
-        } else {
-            Ordering::Greater
-        }
+        // Byte-wise lexicographic order, the same as memcmp followed by a
+        // length comparison.
+        self.data.cmp(b.data)
     }
 }
 
-impl Index<usize> for Slice {
-    type Output = u8;
+impl<'a> Deref for Slice<'a> {
+    type Target = [u8];
 
-    /// Return the ith byte in the referenced data.
-    /// REQUIRES: index < size()
-    fn index(&self, index: usize) -> &u8 {
-        assert!(index < self.size);
-        unsafe {
-            &*self.data.offset(index as isize)
-        }
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> AsRef<[u8]> for Slice<'a> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> PartialOrd for Slice<'a> {
+    fn partial_cmp(&self, other: &Slice<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl<'a> From<&'a [u8]> for Slice {
+impl<'a> Ord for Slice<'a> {
+    fn cmp(&self, other: &Slice<'a>) -> Ordering {
+        self.compare(other)
+    }
+}
+
+/// Formats the referenced data as a string, replacing invalid UTF-8
+/// sequences with U+FFFD.
+impl<'a> fmt::Display for Slice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.data))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Slice<'a> {
     #[inline]
     fn from(s: &'a [u8]) -> Self {
-        Slice::new(s.as_ptr(), s.len())
+        Self { data: s }
     }
 }
 
-impl<'a> From<&'a Vec<u8>> for Slice {
+impl<'a> From<&'a Vec<u8>> for Slice<'a> {
     #[inline]
-    fn from(v: &'a Vec<u8>) -> Self { Slice::new(v[..].as_ptr(), v.len()) }
+    fn from(v: &'a Vec<u8>) -> Self {
+        Self { data: v.as_slice() }
+    }
 }
 
-impl<'a> From<&'a str> for Slice {
+impl<'a> From<&'a str> for Slice<'a> {
     #[inline]
-    fn from(s: &'a str) -> Self { Slice::new(s.as_ptr(), s.len()) }
+    fn from(s: &'a str) -> Self {
+        Self { data: s.as_bytes() }
+    }
 }
 
-impl From<String> for Slice {
+impl<'a> From<&'a String> for Slice<'a> {
     #[inline]
-    fn from(s: String) -> Self { Slice::new(s.as_ptr(), s.len()) }
+    fn from(s: &'a String) -> Self {
+        Self { data: s.as_bytes() }
+    }
 }
 
-impl PartialEq for Slice {
-    fn eq(&self, other: &Slice) -> bool {
-        self.compare(other) == Ordering::Equal
+#[cfg(test)]
+mod tests {
+    use super::Slice;
+    use std::cmp::Ordering;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn basic() {
+        let s = String::from("hello");
+        let mut slice = Slice::from(&s);
+        assert_eq!(slice.size(), 5);
+        assert!(!slice.empty());
+        assert_eq!(slice[1], b'e');
+        assert_eq!(&slice[1..3], b"el");
+        assert_eq!(slice.to_string(), "hello");
+
+        assert!(slice.starts_with(&Slice::from("he")));
+        assert!(!slice.starts_with(&Slice::from("hello!")));
+
+        slice.remove_prefix(2);
+        assert_eq!(slice.slice_data(), b"llo");
+
+        slice.clear();
+        assert!(slice.empty());
+        assert_eq!(slice, Slice::new_empty());
+    }
+
+    #[test]
+    fn compare() {
+        let a = Slice::from("a");
+        let ab = Slice::from("ab");
+        let b = Slice::from("b");
+        assert_eq!(a.compare(&a), Ordering::Equal);
+        assert_eq!(a.compare(&ab), Ordering::Less);
+        assert_eq!(ab.compare(&b), Ordering::Less);
+        assert_eq!(b.compare(&ab), Ordering::Greater);
+        assert_eq!(Slice::new_empty().compare(&a), Ordering::Less);
+        assert_eq!(Slice::from(&[0xffu8][..]).compare(&a), Ordering::Greater);
+
+        let set: BTreeSet<Slice> = vec![b, ab, a].into_iter().collect();
+        let sorted: Vec<&[u8]> = set.iter().map(|s| s.as_ref()).collect();
+        assert_eq!(sorted, vec![&b"a"[..], &b"ab"[..], &b"b"[..]]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn raw_parts() {
+        let v = vec![1u8, 2, 3];
+        let slice = unsafe { Slice::new(v.as_ptr(), v.len()) };
+        assert_eq!(slice.raw_ptr_data(), v.as_ptr());
+        assert_eq!(slice, Slice::from(&v));
+
+        let empty = unsafe { Slice::new(std::ptr::null(), 0) };
+        assert!(empty.empty());
+    }
+}
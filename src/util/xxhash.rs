@@ -0,0 +1,358 @@
+// Copyright (c) 2021, storagezhang <storagezhang@outlook.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file. See the AUTHORS file for names of contributors.
+
+use crate::util::coding::{decode_fixed_32, decode_fixed_64, encode_fixed_64};
+
+const PRIME32_1: u64 = 0x9e3779b1;
+const PRIME32_2: u64 = 0x85ebca77;
+const PRIME32_3: u64 = 0xc2b2ae3d;
+
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+
+const PRIME_MX1: u64 = 0x165667919e3779f9;
+const PRIME_MX2: u64 = 0x9fb21c651e98df25;
+
+// Default XXH3 secret.
+const SECRET_SIZE: usize = 192;
+const SECRET: [u8; SECRET_SIZE] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+const STRIPE_LEN: usize = 64;
+const SECRET_CONSUME_RATE: usize = 8;
+const SECRET_SIZE_MIN: usize = 136;
+const SECRET_LASTACC_START: usize = 7;
+const SECRET_MERGEACCS_START: usize = 11;
+const MIDSIZE_STARTOFFSET: usize = 3;
+const MIDSIZE_LASTOFFSET: usize = 17;
+
+#[inline]
+fn read32(data: &[u8], offset: usize) -> u64 {
+    decode_fixed_32(&data[offset..]) as u64
+}
+
+#[inline]
+fn read64(data: &[u8], offset: usize) -> u64 {
+    decode_fixed_64(&data[offset..])
+}
+
+/// Return the XXH64 hash of `data` with the given `seed`.
+pub fn xxh64(data: &[u8], seed: u64) -> u64 {
+    fn round(acc: u64, input: u64) -> u64 {
+        acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME64_1)
+    }
+
+    fn merge_round(acc: u64, val: u64) -> u64 {
+        (acc ^ round(0, val))
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4)
+    }
+
+    let len = data.len();
+    let mut i = 0;
+    let mut h = if len >= 32 {
+        let mut v1 = seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2);
+        let mut v2 = seed.wrapping_add(PRIME64_2);
+        let mut v3 = seed;
+        let mut v4 = seed.wrapping_sub(PRIME64_1);
+        while i + 32 <= len {
+            v1 = round(v1, read64(data, i));
+            v2 = round(v2, read64(data, i + 8));
+            v3 = round(v3, read64(data, i + 16));
+            v4 = round(v4, read64(data, i + 24));
+            i += 32;
+        }
+        let mut h = v1
+            .rotate_left(1)
+            .wrapping_add(v2.rotate_left(7))
+            .wrapping_add(v3.rotate_left(12))
+            .wrapping_add(v4.rotate_left(18));
+        h = merge_round(h, v1);
+        h = merge_round(h, v2);
+        h = merge_round(h, v3);
+        merge_round(h, v4)
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+
+    h = h.wrapping_add(len as u64);
+
+    while i + 8 <= len {
+        h ^= round(0, read64(data, i));
+        h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+        i += 8;
+    }
+    if i + 4 <= len {
+        h ^= read32(data, i).wrapping_mul(PRIME64_1);
+        h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+        i += 4;
+    }
+    for &b in &data[i..] {
+        h ^= (b as u64).wrapping_mul(PRIME64_5);
+        h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+
+    xxh64_avalanche(h)
+}
+
+/// Return the 64-bit XXH3 hash of `data`.
+#[inline]
+pub fn xxh3_64(data: &[u8]) -> u64 {
+    xxh3_64_with_seed(data, 0)
+}
+
+/// Return the 64-bit XXH3 hash of `data` with the given `seed`.
+pub fn xxh3_64_with_seed(data: &[u8], seed: u64) -> u64 {
+    let len = data.len();
+    if len <= 16 {
+        xxh3_len_0to16(data, &SECRET, seed)
+    } else if len <= 128 {
+        xxh3_len_17to128(data, &SECRET, seed)
+    } else if len <= 240 {
+        xxh3_len_129to240(data, &SECRET, seed)
+    } else if seed == 0 {
+        xxh3_hash_long(data, &SECRET)
+    } else {
+        let mut secret = [0u8; SECRET_SIZE];
+        for i in 0..SECRET_SIZE / 16 {
+            encode_fixed_64(&mut secret[16 * i..], read64(&SECRET, 16 * i).wrapping_add(seed));
+            encode_fixed_64(&mut secret[16 * i + 8..], read64(&SECRET, 16 * i + 8).wrapping_sub(seed));
+        }
+        xxh3_hash_long(data, &secret)
+    }
+}
+
+#[inline]
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+#[inline]
+fn xxh3_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ (h >> 32)
+}
+
+#[inline]
+fn rrmxmx(mut h: u64, len: usize) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^= (h >> 35).wrapping_add(len as u64);
+    h = h.wrapping_mul(PRIME_MX2);
+    h ^ (h >> 28)
+}
+
+#[inline]
+fn mul128_fold64(lhs: u64, rhs: u64) -> u64 {
+    let product = (lhs as u128).wrapping_mul(rhs as u128);
+    (product as u64) ^ ((product >> 64) as u64)
+}
+
+#[inline]
+fn mix16b(data: &[u8], offset: usize, secret: &[u8], secret_offset: usize, seed: u64) -> u64 {
+    let lo = read64(data, offset);
+    let hi = read64(data, offset + 8);
+    mul128_fold64(
+        lo ^ read64(secret, secret_offset).wrapping_add(seed),
+        hi ^ read64(secret, secret_offset + 8).wrapping_sub(seed),
+    )
+}
+
+fn xxh3_len_0to16(data: &[u8], secret: &[u8], seed: u64) -> u64 {
+    let len = data.len();
+    if len > 8 {
+        let bitflip1 = (read64(secret, 24) ^ read64(secret, 32)).wrapping_add(seed);
+        let bitflip2 = (read64(secret, 40) ^ read64(secret, 48)).wrapping_sub(seed);
+        let input_lo = read64(data, 0) ^ bitflip1;
+        let input_hi = read64(data, len - 8) ^ bitflip2;
+        let acc = (len as u64)
+            .wrapping_add(input_lo.swap_bytes())
+            .wrapping_add(input_hi)
+            .wrapping_add(mul128_fold64(input_lo, input_hi));
+        xxh3_avalanche(acc)
+    } else if len >= 4 {
+        let seed = seed ^ (((seed as u32).swap_bytes() as u64) << 32);
+        let input1 = read32(data, 0);
+        let input2 = read32(data, len - 4);
+        let bitflip = (read64(secret, 8) ^ read64(secret, 16)).wrapping_sub(seed);
+        let input64 = input2.wrapping_add(input1 << 32);
+        rrmxmx(input64 ^ bitflip, len)
+    } else if len > 0 {
+        let c1 = data[0] as u32;
+        let c2 = data[len >> 1] as u32;
+        let c3 = data[len - 1] as u32;
+        let combined = (c1 << 16) | (c2 << 24) | c3 | ((len as u32) << 8);
+        let bitflip = (read32(secret, 0) ^ read32(secret, 4)).wrapping_add(seed);
+        xxh64_avalanche(combined as u64 ^ bitflip)
+    } else {
+        xxh64_avalanche(seed ^ read64(secret, 56) ^ read64(secret, 64))
+    }
+}
+
+fn xxh3_len_17to128(data: &[u8], secret: &[u8], seed: u64) -> u64 {
+    let len = data.len();
+    let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+    if len > 32 {
+        if len > 64 {
+            if len > 96 {
+                acc = acc.wrapping_add(mix16b(data, 48, secret, 96, seed));
+                acc = acc.wrapping_add(mix16b(data, len - 64, secret, 112, seed));
+            }
+            acc = acc.wrapping_add(mix16b(data, 32, secret, 64, seed));
+            acc = acc.wrapping_add(mix16b(data, len - 48, secret, 80, seed));
+        }
+        acc = acc.wrapping_add(mix16b(data, 16, secret, 32, seed));
+        acc = acc.wrapping_add(mix16b(data, len - 32, secret, 48, seed));
+    }
+    acc = acc.wrapping_add(mix16b(data, 0, secret, 0, seed));
+    acc = acc.wrapping_add(mix16b(data, len - 16, secret, 16, seed));
+    xxh3_avalanche(acc)
+}
+
+fn xxh3_len_129to240(data: &[u8], secret: &[u8], seed: u64) -> u64 {
+    let len = data.len();
+    let nb_rounds = len / 16;
+    let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+    for i in 0..8 {
+        acc = acc.wrapping_add(mix16b(data, 16 * i, secret, 16 * i, seed));
+    }
+    let mut acc_end = mix16b(data, len - 16, secret, SECRET_SIZE_MIN - MIDSIZE_LASTOFFSET, seed);
+    acc = xxh3_avalanche(acc);
+    for i in 8..nb_rounds {
+        acc_end = acc_end.wrapping_add(mix16b(
+            data,
+            16 * i,
+            secret,
+            16 * (i - 8) + MIDSIZE_STARTOFFSET,
+            seed,
+        ));
+    }
+    xxh3_avalanche(acc.wrapping_add(acc_end))
+}
+
+#[inline]
+fn accumulate_512(acc: &mut [u64; 8], data: &[u8], offset: usize, secret: &[u8], secret_offset: usize) {
+    for i in 0..8 {
+        let data_val = read64(data, offset + 8 * i);
+        let data_key = data_val ^ read64(secret, secret_offset + 8 * i);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(data_val);
+        acc[i] = acc[i].wrapping_add((data_key & 0xffffffff).wrapping_mul(data_key >> 32));
+    }
+}
+
+#[inline]
+fn scramble_acc(acc: &mut [u64; 8], secret: &[u8], secret_offset: usize) {
+    for (i, a) in acc.iter_mut().enumerate() {
+        let key64 = read64(secret, secret_offset + 8 * i);
+        let mut acc64 = *a;
+        acc64 ^= acc64 >> 47;
+        acc64 ^= key64;
+        *a = acc64.wrapping_mul(PRIME32_1);
+    }
+}
+
+fn xxh3_hash_long(data: &[u8], secret: &[u8]) -> u64 {
+    let len = data.len();
+    let secret_size = secret.len();
+    let mut acc: [u64; 8] = [
+        PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1,
+    ];
+
+    let nb_stripes_per_block = (secret_size - STRIPE_LEN) / SECRET_CONSUME_RATE;
+    let block_len = STRIPE_LEN * nb_stripes_per_block;
+    let nb_blocks = (len - 1) / block_len;
+
+    for n in 0..nb_blocks {
+        for s in 0..nb_stripes_per_block {
+            accumulate_512(&mut acc, data, n * block_len + s * STRIPE_LEN, secret, s * SECRET_CONSUME_RATE);
+        }
+        scramble_acc(&mut acc, secret, secret_size - STRIPE_LEN);
+    }
+
+    // Last partial block
+    let nb_stripes = ((len - 1) - block_len * nb_blocks) / STRIPE_LEN;
+    for s in 0..nb_stripes {
+        accumulate_512(&mut acc, data, nb_blocks * block_len + s * STRIPE_LEN, secret, s * SECRET_CONSUME_RATE);
+    }
+
+    // Last stripe
+    accumulate_512(
+        &mut acc,
+        data,
+        len - STRIPE_LEN,
+        secret,
+        secret_size - STRIPE_LEN - SECRET_LASTACC_START,
+    );
+
+    let mut result = (len as u64).wrapping_mul(PRIME64_1);
+    for i in 0..4 {
+        result = result.wrapping_add(mul128_fold64(
+            acc[2 * i] ^ read64(secret, SECRET_MERGEACCS_START + 16 * i),
+            acc[2 * i + 1] ^ read64(secret, SECRET_MERGEACCS_START + 16 * i + 8),
+        ));
+    }
+    xxh3_avalanche(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{xxh3_64, xxh3_64_with_seed, xxh64};
+
+    #[test]
+    fn xxh64_results() {
+        assert_eq!(xxh64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(xxh64(b"a", 0), 0xd24ec4f1a98c6e5b);
+        assert_eq!(xxh64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(xxh64(b"message digest", 0), 0x066ed728fceeb3be);
+        assert_eq!(xxh64(b"hello world", 0), 0x45ab6734b21e6968);
+
+        let data: Vec<u8> = (0..2048u32).map(|i| i as u8).collect();
+        assert_eq!(xxh64(&data[..17], 0), 0x5603e60c527599b6);
+        assert_eq!(xxh64(&data[..100], 0), 0x6ac1e58032166597);
+        assert_eq!(xxh64(&data[..200], 0), 0x50dc1079b99e879c);
+        assert_eq!(xxh64(&data, 0), 0x68534a48b7bf5f4d);
+    }
+
+    #[test]
+    fn xxh3_results() {
+        assert_eq!(xxh3_64(b""), 0x2d06800538d394c2);
+        assert_eq!(xxh3_64(b"a"), 0xe6c632b61e964e1f);
+        assert_eq!(xxh3_64(b"abc"), 0x78af5f94892f3950);
+        assert_eq!(xxh3_64(b"message digest"), 0x160d8e9329be94f9);
+        assert_eq!(xxh3_64(b"hello world"), 0xd447b1ea40e6988b);
+
+        // One input for each length class: 17-128, 129-240 and long inputs.
+        let data: Vec<u8> = (0..2048u32).map(|i| i as u8).collect();
+        assert_eq!(xxh3_64(&data[..17]), 0x9ef341a99de37328);
+        assert_eq!(xxh3_64(&data[..100]), 0x004e4f921a64bd1c);
+        assert_eq!(xxh3_64(&data[..200]), 0xf42a8864feaf0703);
+        assert_eq!(xxh3_64(&data), 0xdd420471ff96bd00);
+        assert_eq!(xxh3_64_with_seed(&data[..17], 301), 0x52fda3a6c7bd8992);
+        assert_eq!(xxh3_64_with_seed(&data[..100], 301), 0xf743f02e2b87b356);
+        assert_eq!(xxh3_64_with_seed(&data[..200], 301), 0xbe0357617429fede);
+        assert_eq!(xxh3_64_with_seed(&data, 301), 0x0e0c5afaeb4ea719);
+    }
+}